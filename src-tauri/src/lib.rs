@@ -1,5 +1,6 @@
 mod commands;
 mod utils;
+pub mod sudo;
 
 pub use commands::*;
 
@@ -2,28 +2,55 @@
 
 pub mod commands;
 pub mod utils;
-pub mod sudo;
 
-use sudo::{SudoCache, fast_sudo, clear_sudo_cache, direct_privilege_escalation, check_sudo_privileges};
-use tauri::Manager;
+use std::sync::Arc;
+use term::sudo::{
+    self, keystore, ActiveBackend, Confirmations, InFlightRequests, KeyringStore, PendingPrompts,
+    PrivilegeBackend, RateLimiter, RunningCommands, SharedPasswordStore, SharedRunner, SudoCache,
+    SudoConfig, SudoKeepalive, SystemRunner,
+};
+use tauri::{Manager, RunEvent};
 
 fn main() {
     dotenvy::dotenv().ok();
 
     tauri::Builder::default()
         .setup(|app| {
+            sudo::disable_core_dumps();
             app.manage(SudoCache::new());
-            
-            let cache = app.state::<SudoCache>();
-            let cache_clone = cache.inner().clone();
-            
-            std::thread::spawn(move || {
-                loop {
-                    std::thread::sleep(std::time::Duration::from_secs(300));
-                    cache_clone.clear_expired(15);
-                }
-            });
-            
+            let backend = PrivilegeBackend::detect();
+            let mut config = SudoConfig::default();
+            // Expire along with sudo's own timestamp when it isn't the default
+            if let Some(minutes) = sudo::detect_timeout(&SystemRunner, backend) {
+                config.timeout_minutes = minutes;
+            }
+            app.manage(config);
+            sudo::init_logging(&app.state::<SudoConfig>());
+            // Probed once here, requests reuse the answer
+            if !backend.installed() {
+                tracing::warn!(backend = backend.program(), "privilege backend not installed");
+            }
+            if let Some(version) = sudo::detect_version(&SystemRunner, backend) {
+                tracing::debug!(version = %version.raw, "detected sudo version");
+            }
+            app.manage(ActiveBackend::new(backend));
+            app.manage::<SharedRunner>(Arc::new(SystemRunner));
+            let keyring_service = app.state::<SudoConfig>().keyring_service.clone();
+            app.manage::<SharedPasswordStore>(Arc::new(KeyringStore::new(
+                keyring_service,
+                keystore::current_user_name(),
+            )));
+            app.manage(SudoKeepalive::new());
+            app.manage(RunningCommands::new());
+            app.manage(InFlightRequests::new());
+            app.manage(RateLimiter::new());
+            app.manage(PendingPrompts::new());
+            app.manage(Confirmations::new());
+
+            let sweep_interval = app.state::<SudoConfig>().sweep_interval_secs;
+            app.state::<SudoCache>()
+                .start_sweeper(std::time::Duration::from_secs(sweep_interval));
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -36,11 +63,46 @@ fn main() {
             commands::api_key::get_api_key,
             commands::api_key::validate_api_key,
             commands::api_key::delete_api_key,
-            fast_sudo,
-            clear_sudo_cache,
-            direct_privilege_escalation,
-            check_sudo_privileges
+            sudo::fast_sudo,
+            sudo::fast_sudo_batch,
+            sudo::fast_sudo_stream,
+            sudo::clear_sudo_cache,
+            sudo::clear_sudo_cache_for_user,
+            sudo::direct_privilege_escalation,
+            sudo::check_sudo_privileges,
+            sudo::sudo_cache_status,
+            sudo::start_sudo_keepalive,
+            sudo::stop_sudo_keepalive,
+            sudo::list_sudo_sessions,
+            sudo::validate_sudoers,
+            sudo::cancel_sudo,
+            sudo::list_sudo_permissions,
+            sudo::store_sudo_password,
+            sudo::clear_sudo_password,
+            sudo::user_can_sudo,
+            sudo::detect_sudo_timeout,
+            sudo::prime_sudo,
+            sudo::sudo_prompt_text,
+            sudo::respond_sudo_prompt,
+            sudo::sudo_version,
+            sudo::lock_sudo,
+            sudo::can_run,
+            sudo::verify_audit_log,
+            sudo::sudo_diagnostics,
+            sudo::sudo_self_test,
+            sudo::list_running_sudo,
+            sudo::set_privilege_backend,
+            sudo::sudo_systemctl_status
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // Nothing elevated should outlive the app, nor its cached auth
+            if let RunEvent::Exit = event {
+                app.state::<RunningCommands>().kill_all();
+                let runner = app.state::<SharedRunner>();
+                let backend = app.state::<ActiveBackend>().get();
+                app.state::<SudoCache>().shutdown(runner.as_ref(), backend);
+            }
+        });
 }
\ No newline at end of file
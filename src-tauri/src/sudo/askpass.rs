@@ -0,0 +1,109 @@
+// src-tauri/src/sudo/askpass.rs
+use nix::fcntl::OFlag;
+use nix::sys::stat::Mode;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use zeroize::Zeroizing;
+
+// Helper protocol: `sudo -A` runs $SUDO_ASKPASS with the prompt as its only
+// argument and reads the password from the helper's stdout, up to a newline.
+// Ours is a shell script that cats a FIFO next to it, in a directory only we
+// can enter. The app writes the password into the FIFO, so it only passes
+// through the kernel and never lands on disk, in argv or in the environment.
+pub struct Askpass {
+    dir: PathBuf,
+    writer: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Askpass {
+    pub fn create() -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "term-askpass-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Fails if the path already exists, so nobody can plant a directory
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        // Cleaned up on drop from here on, even if the rest fails
+        let askpass = Self { dir, writer: None };
+
+        nix::unistd::mkfifo(&askpass.fifo(), Mode::S_IRUSR | Mode::S_IWUSR)?;
+
+        let fifo = askpass.fifo();
+        let fifo = shlex::try_quote(&fifo.to_string_lossy())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+            .into_owned();
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o700)
+            .open(askpass.helper())?
+            .write_all(format!("#!/bin/sh\nexec cat {}\n", fifo).as_bytes())?;
+
+        Ok(askpass)
+    }
+
+    // What SUDO_ASKPASS should point at
+    pub fn helper(&self) -> PathBuf {
+        self.dir.join("askpass")
+    }
+
+    fn fifo(&self) -> PathBuf {
+        self.dir.join("password")
+    }
+
+    // Starts a background thread that hands `input` (the password and its
+    // newline) to the helper. The first helper to run gets it; any retry
+    // after sudo rejects it gets EOF, which makes sudo give up instead of
+    // waiting on a helper that will never answer. Serves until dropped.
+    pub fn serve(&mut self, input: &[u8]) {
+        if self.writer.is_some() {
+            return;
+        }
+
+        let fifo = self.fifo();
+        let done = Arc::new(AtomicBool::new(false));
+        let mut input = Some(Zeroizing::new(input.to_vec()));
+        let writer = {
+            let done = done.clone();
+            std::thread::spawn(move || loop {
+                // Blocks until something opens the read end
+                let mut pipe = match OpenOptions::new().write(true).open(&fifo) {
+                    Ok(pipe) => pipe,
+                    Err(_) => break,
+                };
+                if done.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Some(input) = input.take() {
+                    let _ = pipe.write_all(&input);
+                }
+            })
+        };
+        self.writer = Some((done, writer));
+    }
+}
+
+impl Drop for Askpass {
+    fn drop(&mut self) {
+        if let Some((done, writer)) = self.writer.take() {
+            // Opening the read end without blocking releases the writer if
+            // it's still waiting for a helper that sudo never started. Held
+            // until the writer is gone, so its next open can't block either.
+            done.store(true, Ordering::SeqCst);
+            let _release = OpenOptions::new()
+                .read(true)
+                .custom_flags(OFlag::O_NONBLOCK.bits())
+                .open(self.fifo());
+            let _ = writer.join();
+        }
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,382 @@
+// src-tauri/src/sudo/audit.rs
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+use super::keystore::{KeyringStore, PasswordStore};
+use super::{SudoConfig, SudoResponse};
+
+// Where the HMAC key for SudoConfig.audit_signing comes from
+#[derive(Clone)]
+pub enum AuditKey {
+    Secret(Zeroizing<Vec<u8>>),
+    // A random key kept in the OS keyring under SudoConfig.keyring_service,
+    // created on first use
+    Keyring,
+}
+
+// Written out by hand so the secret never ends up in a log line
+impl std::fmt::Debug for AuditKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditKey::Secret(_) => f.write_str("Secret(<redacted>)"),
+            AuditKey::Keyring => f.write_str("Keyring"),
+        }
+    }
+}
+
+// What verify found in a signed log
+#[derive(Serialize, Debug)]
+pub struct AuditVerification {
+    pub entries: usize,
+    // Line number, counting from 1, of the first entry that was changed,
+    // removed from before it, or never signed. Later ones aren't checked.
+    pub first_broken: Option<usize>,
+    // Cutting entries off the end leaves a valid chain, which only shows by
+    // comparing this against a value noted down earlier
+    pub last_mac: Option<String>,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Keyring entry under SudoConfig.keyring_service holding AuditKey::Keyring
+const KEYRING_ENTRY: &str = "audit-hmac-key";
+
+// Ends every signed line, right before the closing brace
+const MAC_FIELD: &str = ",\"mac\":\"";
+
+static KEYRING_KEY: OnceLock<Zeroizing<Vec<u8>>> = OnceLock::new();
+
+// The log the chain was last extended in and the mac it ended with. The lock
+// also keeps two records from both chaining off the same entry.
+static CHAIN: Mutex<Option<(PathBuf, String)>> = Mutex::new(None);
+
+// One line of the audit log. Built only from the command and its outcome, so
+// the password can't end up in here.
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    timestamp: u64,
+    uid: u32,
+    command: &'a str,
+    args: &'a [String],
+    success: bool,
+    exit_code: Option<i32>,
+    cached: bool,
+    method: &'a str,
+}
+
+// $XDG_STATE_HOME/term/sudo-audit.log, or the local data dir on platforms
+// without a state dir
+pub fn default_log_path() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("term").join("sudo-audit.log"))
+}
+
+// Appends a JSON line for a command that ran with elevation. Failing to write
+// the log is reported but never blocks the command's result.
+pub fn record(
+    config: &SudoConfig,
+    uid: u32,
+    command: &str,
+    args: &[String],
+    response: &SudoResponse,
+) {
+    let path = match config.audit_log.clone().or_else(default_log_path) {
+        Some(path) => path,
+        None => return,
+    };
+
+    let entry = AuditEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default(),
+        uid,
+        command,
+        args,
+        success: response.success,
+        exit_code: response.exit_code,
+        cached: response.cached,
+        method: &response.method,
+    };
+
+    let result = serde_json::to_string(&entry)
+        .map_err(io::Error::from)
+        .and_then(|line| match config.audit_signing {
+            Some(_) => append_signed(config, &path, &line),
+            None => append(&path, &line, config.audit_max_bytes),
+        });
+
+    if let Err(e) = result {
+        tracing::warn!(path = %path.display(), error = %e, "failed to write audit log");
+    }
+}
+
+// Each entry carries an HMAC over the mac before it and its own content, so
+// editing, reordering or removing entries breaks the chain from there on. A
+// rotated-in file starts a chain of its own.
+fn append_signed(config: &SudoConfig, path: &Path, body: &str) -> io::Result<()> {
+    let key = signing_key(config, true).map_err(io::Error::other)?;
+    let mut chain = CHAIN
+        .lock()
+        .map_err(|_| io::Error::other("audit chain is unavailable"))?;
+    let previous = match chain.as_ref() {
+        Some((chained, mac)) if chained == path => mac.clone(),
+        _ => last_mac(path)?,
+    };
+
+    let mut mac = entry_mac(&key, &previous, body);
+    // Signed lines all have the same length whatever the chain
+    let line = signed_line(body, &mac);
+    if needs_rotation(path, line.len(), config.audit_max_bytes) {
+        mac = entry_mac(&key, "", body);
+    }
+    append(path, &signed_line(body, &mac), config.audit_max_bytes)?;
+    *chain = Some((path.to_path_buf(), mac));
+    Ok(())
+}
+
+// Recomputes the chain in a log written with audit_signing set
+pub fn verify(config: &SudoConfig, path: &Path) -> Result<AuditVerification, String> {
+    let key = signing_key(config, false)?;
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read audit log {}: {}", path.display(), e))?;
+
+    let mut verification = AuditVerification {
+        entries: 0,
+        first_broken: None,
+        last_mac: None,
+    };
+    let mut previous = String::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        verification.entries += 1;
+        let signed = split_mac(line).and_then(|(body, mac)| Some((body, decode_hex(mac)?, mac)));
+        let intact = signed.filter(|(body, expected, _)| {
+            chain_hmac(&key, &previous, body).verify_slice(expected).is_ok()
+        });
+        match intact {
+            Some((_, _, mac)) => previous = mac.to_string(),
+            None => {
+                verification.first_broken = Some(index + 1);
+                verification.last_mac = None;
+                return Ok(verification);
+            }
+        }
+    }
+    verification.last_mac = (!previous.is_empty()).then_some(previous);
+    Ok(verification)
+}
+
+fn signing_key(config: &SudoConfig, create: bool) -> Result<Zeroizing<Vec<u8>>, String> {
+    match &config.audit_signing {
+        None => Err("Audit log signing is not configured".to_string()),
+        Some(AuditKey::Secret(secret)) => Ok(secret.clone()),
+        Some(AuditKey::Keyring) => {
+            if let Some(key) = KEYRING_KEY.get() {
+                return Ok(key.clone());
+            }
+            let key = keyring_key(&config.keyring_service, create)?;
+            Ok(KEYRING_KEY.get_or_init(|| key).clone())
+        }
+    }
+}
+
+fn keyring_key(service: &str, create: bool) -> Result<Zeroizing<Vec<u8>>, String> {
+    let store = KeyringStore::new(service, KEYRING_ENTRY);
+    if let Some(stored) = store.get()? {
+        return decode_hex(&stored)
+            .map(Zeroizing::new)
+            .ok_or_else(|| "Audit key in the keyring is malformed".to_string());
+    }
+    if !create {
+        return Err("No audit key in the keyring".to_string());
+    }
+
+    let mut key = Zeroizing::new(vec![0u8; 32]);
+    getrandom::getrandom(&mut key).map_err(|e| format!("Failed to generate audit key: {}", e))?;
+    store.set(&Zeroizing::new(encode_hex(&key)))?;
+    Ok(key)
+}
+
+// The mac a log ends on, empty for a missing or empty log
+fn last_mac(path: &Path) -> io::Result<String> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e),
+    };
+    let last = content.lines().rev().find(|line| !line.is_empty());
+    Ok(last
+        .and_then(split_mac)
+        .map(|(_, mac)| mac.to_string())
+        .unwrap_or_default())
+}
+
+fn chain_hmac(key: &[u8], previous: &str, body: &str) -> HmacSha256 {
+    let mut hmac = HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    hmac.update(previous.as_bytes());
+    hmac.update(b"\n");
+    hmac.update(body.as_bytes());
+    hmac
+}
+
+fn entry_mac(key: &[u8], previous: &str, body: &str) -> String {
+    encode_hex(&chain_hmac(key, previous, body).finalize().into_bytes())
+}
+
+// `{...}` becomes `{...,"mac":"<hex>"}`
+fn signed_line(body: &str, mac: &str) -> String {
+    let open = body.strip_suffix('}').unwrap_or(body);
+    format!("{}{}{}\"}}", open, MAC_FIELD, mac)
+}
+
+// The entry as it was signed, and its mac. Quotes inside JSON strings are
+// escaped, so MAC_FIELD can't turn up in a value.
+fn split_mac(line: &str) -> Option<(String, &str)> {
+    let rest = line.strip_suffix("\"}")?;
+    let at = rest.rfind(MAC_FIELD)?;
+    Some((format!("{}}}", &rest[..at]), &rest[at + MAC_FIELD.len()..]))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// None for anything but pairs of hex digits
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+// Once the cap would be exceeded, keep a single previous generation as
+// `<path>.1` and start over. A cap of 0 lets the log grow unbounded.
+fn needs_rotation(path: &Path, line_len: usize, max_bytes: u64) -> bool {
+    let len = fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    max_bytes > 0 && len > 0 && len + line_len as u64 + 1 > max_bytes
+}
+
+fn append(path: &Path, line: &str, max_bytes: u64) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    if needs_rotation(path, line.len(), max_bytes) {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(".1");
+        fs::rename(path, rotated)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_config(secret: &[u8]) -> SudoConfig {
+        SudoConfig {
+            audit_signing: Some(AuditKey::Secret(Zeroizing::new(secret.to_vec()))),
+            audit_max_bytes: 0,
+            ..Default::default()
+        }
+    }
+
+    fn log_path(name: &str) -> PathBuf {
+        let name = format!("term-audit-{}-{}.log", std::process::id(), name);
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn write_log(config: &SudoConfig, path: &Path, entries: usize) {
+        for n in 0..entries {
+            append_signed(config, path, &format!("{{\"n\":{}}}", n)).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_signed_log_verifies() {
+        let config = signing_config(b"secret");
+        let path = log_path("intact");
+        write_log(&config, &path, 3);
+
+        let verification = verify(&config, &path).unwrap();
+        assert_eq!(verification.entries, 3);
+        assert_eq!(verification.first_broken, None);
+        assert_eq!(verification.last_mac, Some(last_mac(&path).unwrap()));
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn edits_and_removals_break_the_chain() {
+        let config = signing_config(b"secret");
+        let path = log_path("tampered");
+        write_log(&config, &path, 3);
+        let content = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, content.replace("\"n\":1", "\"n\":7")).unwrap();
+        let verification = verify(&config, &path).unwrap();
+        assert_eq!(verification.first_broken, Some(2));
+        assert_eq!(verification.last_mac, None);
+
+        let without_first: Vec<&str> = content.lines().skip(1).collect();
+        fs::write(&path, without_first.join("\n")).unwrap();
+        assert_eq!(verify(&config, &path).unwrap().first_broken, Some(1));
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn another_key_fails_verification() {
+        let path = log_path("rekeyed");
+        write_log(&signing_config(b"secret"), &path, 1);
+        let verification = verify(&signing_config(b"other"), &path).unwrap();
+        assert_eq!(verification.first_broken, Some(1));
+        assert!(verify(&SudoConfig::default(), &path).is_err());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn the_mac_comes_back_off_a_signed_line() {
+        let body = "{\"command\":\"/usr/bin/apt\",\"note\":\"a \\\"quoted\\\" mac\"}";
+        let mac = entry_mac(b"key", "", body);
+        let line = signed_line(body, &mac);
+        assert!(line.ends_with(&format!("\"mac\":\"{}\"}}", mac)));
+        assert_eq!(split_mac(&line), Some((body.to_string(), mac.as_str())));
+        assert_eq!(split_mac(body), None);
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let bytes = [0u8, 1, 0x7f, 0xab, 0xff];
+        assert_eq!(encode_hex(&bytes), "00017fabff");
+        assert_eq!(decode_hex("00017fabff").as_deref(), Some(&bytes[..]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
@@ -0,0 +1,344 @@
+// src-tauri/src/sudo/backend.rs
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process::Output;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+use super::{CommandRunner, PasswordMode};
+
+// The tool used to run commands with elevated privileges.
+//
+// doas has no equivalent of `sudo -S`: it only ever reads the password from
+// the controlling terminal, so the app can't verify a password for it. Cached
+// use relies on `persist` (or `nopass`) in doas.conf and on `doas -n`.
+//
+// pkexec hands authentication to the desktop's polkit agent. The app never
+// sees a password and keeps no cache for it; every run may show the dialog.
+//
+// On Windows, UAC plays the same role as pkexec: Windows shows the consent
+// dialog itself. It only reports an exit code, see uac::run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivilegeBackend {
+    Sudo,
+    Doas,
+    Pkexec,
+    Uac,
+}
+
+impl PrivilegeBackend {
+    // Probes PATH for an installed tool, preferring sudo. Falls back to sudo
+    // when none is found so errors mention the more familiar binary.
+    pub fn detect() -> Self {
+        if cfg!(windows) {
+            PrivilegeBackend::Uac
+        } else if binary_exists("sudo") {
+            PrivilegeBackend::Sudo
+        } else if binary_exists("doas") {
+            PrivilegeBackend::Doas
+        } else if binary_exists("pkexec") {
+            PrivilegeBackend::Pkexec
+        } else {
+            PrivilegeBackend::Sudo
+        }
+    }
+
+    // By the names the frontend uses, see the serde renaming above
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sudo" => Some(PrivilegeBackend::Sudo),
+            "doas" => Some(PrivilegeBackend::Doas),
+            "pkexec" => Some(PrivilegeBackend::Pkexec),
+            "uac" => Some(PrivilegeBackend::Uac),
+            _ => None,
+        }
+    }
+
+    pub fn is_installed(self) -> bool {
+        match self {
+            PrivilegeBackend::Uac => cfg!(windows),
+            _ => binary_exists(self.program()),
+        }
+    }
+
+    // is_installed, probed once per backend and then remembered, for the
+    // request path where a PATH search per run would add up
+    pub fn installed(self) -> bool {
+        static INSTALLED: [OnceLock<bool>; 4] = [
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+            OnceLock::new(),
+        ];
+        *INSTALLED[self as usize].get_or_init(|| self.is_installed())
+    }
+
+    pub fn program(self) -> &'static str {
+        match self {
+            PrivilegeBackend::Sudo => "sudo",
+            PrivilegeBackend::Doas => "doas",
+            PrivilegeBackend::Pkexec => "pkexec",
+            PrivilegeBackend::Uac => "runas",
+        }
+    }
+
+    // Backends that prompt on their own, bypassing the app's password and cache
+    pub fn handles_authentication(self) -> bool {
+        matches!(self, PrivilegeBackend::Pkexec | PrivilegeBackend::Uac)
+    }
+
+    // Whether a password can be fed on stdin (`sudo -S`)
+    pub fn supports_stdin_password(self) -> bool {
+        matches!(self, PrivilegeBackend::Sudo)
+    }
+
+    // Whether verify can check a password before anything runs
+    pub fn verifies_passwords(self) -> bool {
+        matches!(self, PrivilegeBackend::Sudo)
+    }
+
+    // Checks a password without running anything, with `sudo -v` fed the
+    // password through `mode`. With `ignore_timestamp`, -k makes sudo check
+    // it even while its timestamp is still valid (and leaves the timestamp
+    // alone); otherwise a valid timestamp means the password isn't looked
+    // at. The output is sudo's: success means the password was accepted.
+    // Timing out fails with ErrorKind::TimedOut.
+    pub fn verify(
+        self,
+        runner: &dyn CommandRunner,
+        password: &str,
+        mode: PasswordMode,
+        timeout: Option<Duration>,
+        ignore_timestamp: bool,
+    ) -> io::Result<Verification> {
+        if !self.verifies_passwords() {
+            return Ok(Verification::Unsupported);
+        }
+
+        // No need to wait for the prompt: -S reads a line from the pipe
+        // whenever sudo gets to it, and until then the password sits in the
+        // pipe buffer. stdin is closed afterwards so sudo sees EOF rather
+        // than a retry prompt.
+        let input = password_line(password)?;
+
+        let output = match mode {
+            PasswordMode::Stdin => {
+                let args: &[&str] = if ignore_timestamp {
+                    &["-k", "-S", "-v"]
+                } else {
+                    &["-S", "-v"]
+                };
+                runner.run_with_timeout(self.program(), args, &[], Some(&input), timeout)?
+            }
+            PasswordMode::Askpass => {
+                verify_with_askpass(runner, self, &input, timeout, ignore_timestamp)?
+            }
+        };
+        Ok(Verification::Checked(output))
+    }
+
+    // doas decides what survives through keepenv/setenv in doas.conf
+    pub fn supports_preserve_env(self) -> bool {
+        matches!(self, PrivilegeBackend::Sudo)
+    }
+
+    // `sudo -i`, and `sudo -l` for listing what the user may run
+    pub fn supports_login_shell(self) -> bool {
+        matches!(self, PrivilegeBackend::Sudo)
+    }
+
+    // Flag that picks the target user, followed by the user name
+    pub fn run_as_flag(self) -> &'static str {
+        match self {
+            PrivilegeBackend::Pkexec => "--user",
+            _ => "-u",
+        }
+    }
+
+    // Arguments that extend the backend's timestamp without running anything.
+    // doas `persist` can't be extended, and polkit/UAC keep no timestamp.
+    pub fn refresh_args(self) -> Option<&'static [&'static str]> {
+        match self {
+            PrivilegeBackend::Sudo => Some(&["-n", "-v"]),
+            _ => None,
+        }
+    }
+
+    // Command that drops any cached authentication held by the backend. UAC
+    // asks every time and has nothing to drop.
+    pub fn invalidate_command(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            PrivilegeBackend::Sudo => Some(("sudo", &["-k"])),
+            PrivilegeBackend::Doas => Some(("doas", &["-L"])),
+            PrivilegeBackend::Pkexec => Some(("pkcheck", &["--revoke-temp"])),
+            PrivilegeBackend::Uac => None,
+        }
+    }
+
+    // Explains exit codes the backend itself uses, as opposed to the command's
+    pub fn describe_exit(self, exit_code: Option<i32>) -> Option<&'static str> {
+        match (self, exit_code) {
+            (PrivilegeBackend::Pkexec, Some(126)) => Some("Authentication dialog was dismissed"),
+            (PrivilegeBackend::Pkexec, Some(127)) => Some("Not authorized to run this command"),
+            _ => None,
+        }
+    }
+}
+
+// The backend requests run with, managed as app state so
+// set_privilege_backend can switch it without a restart. Each command reads
+// it once up front and sticks with that for the whole run.
+pub struct ActiveBackend {
+    current: Mutex<PrivilegeBackend>,
+}
+
+impl ActiveBackend {
+    pub fn new(backend: PrivilegeBackend) -> Self {
+        Self {
+            current: Mutex::new(backend),
+        }
+    }
+
+    pub fn get(&self) -> PrivilegeBackend {
+        // Only ever holds a Copy value, so a poisoned lock is still sound
+        match self.current.lock() {
+            Ok(current) => *current,
+            Err(poisoned) => *poisoned.into_inner(),
+        }
+    }
+
+    // Returns the backend that was active before
+    pub fn set(&self, backend: PrivilegeBackend) -> PrivilegeBackend {
+        match self.current.lock() {
+            Ok(mut current) => std::mem::replace(&mut *current, backend),
+            Err(poisoned) => std::mem::replace(&mut *poisoned.into_inner(), backend),
+        }
+    }
+}
+
+pub enum Verification {
+    Checked(Output),
+    // doas only reads a password from the terminal, and polkit and UAC ask
+    // for themselves when the command runs
+    Unsupported,
+}
+
+// What sudo reads for a password: the bytes exactly as given, trailing
+// whitespace included, ended by a single \n. sudo's getln stops at a \r as
+// well as a \n, so a password holding either would be taken as two answers
+// and is refused instead.
+pub(super) fn password_line(password: &str) -> io::Result<Zeroizing<Vec<u8>>> {
+    if password.contains(['\n', '\r']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Password must not contain a line break",
+        ));
+    }
+    let mut line = Zeroizing::new(Vec::with_capacity(password.len() + 1));
+    line.extend_from_slice(password.as_bytes());
+    line.push(b'\n');
+    Ok(line)
+}
+
+#[cfg(unix)]
+fn verify_with_askpass(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+    input: &[u8],
+    timeout: Option<Duration>,
+    ignore_timestamp: bool,
+) -> io::Result<Output> {
+    let mut askpass = super::askpass::Askpass::create()?;
+    askpass.serve(input);
+    let helper = askpass.helper();
+    let args: &[&str] = if ignore_timestamp {
+        &["-k", "-A", "-v"]
+    } else {
+        &["-A", "-v"]
+    };
+    runner.run_with_timeout(
+        backend.program(),
+        args,
+        &[("SUDO_ASKPASS", &helper.to_string_lossy())],
+        None,
+        timeout,
+    )
+}
+
+#[cfg(not(unix))]
+fn verify_with_askpass(
+    _runner: &dyn CommandRunner,
+    _backend: PrivilegeBackend,
+    _input: &[u8],
+    _timeout: Option<Duration>,
+    _ignore_timestamp: bool,
+) -> io::Result<Output> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "askpass mode needs a Unix system",
+    ))
+}
+
+// A PATH search of our own: minimal containers often ship without `which`
+fn binary_exists(name: &str) -> bool {
+    let file = if cfg!(windows) {
+        format!("{}.exe", name)
+    } else {
+        name.to_string()
+    };
+    super::policy::resolve_command(&file).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sudo::MockRunner;
+
+    #[test]
+    fn password_line_keeps_the_password_verbatim() {
+        assert_eq!(&password_line("hunter2").unwrap()[..], b"hunter2\n");
+        assert_eq!(&password_line("  spaced  ").unwrap()[..], b"  spaced  \n");
+        assert_eq!(&password_line("").unwrap()[..], b"\n");
+    }
+
+    #[test]
+    fn password_line_refuses_line_breaks() {
+        for password in ["two\nlines", "carriage\rreturn", "trailing\n"] {
+            let error = password_line(password).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn verify_feeds_sudo_the_password() {
+        let runner = MockRunner::new().reply(0, "", "");
+        let verification = PrivilegeBackend::Sudo
+            .verify(&runner, "hunter2", PasswordMode::Stdin, None, true)
+            .unwrap();
+        assert!(matches!(verification, Verification::Checked(output) if output.status.success()));
+        assert_eq!(runner.calls(), [["sudo", "-k", "-S", "-v"]]);
+
+        let runner = MockRunner::new();
+        let verification = PrivilegeBackend::Doas
+            .verify(&runner, "hunter2", PasswordMode::Stdin, None, false)
+            .unwrap();
+        assert!(matches!(verification, Verification::Unsupported));
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn backends_go_by_their_serde_names() {
+        for backend in [
+            PrivilegeBackend::Sudo,
+            PrivilegeBackend::Doas,
+            PrivilegeBackend::Pkexec,
+            PrivilegeBackend::Uac,
+        ] {
+            let name = serde_json::to_value(backend).unwrap();
+            assert_eq!(PrivilegeBackend::from_name(name.as_str().unwrap()), Some(backend));
+        }
+        assert_eq!(PrivilegeBackend::from_name("su"), None);
+    }
+}
@@ -0,0 +1,153 @@
+// src-tauri/src/sudo/builder.rs
+use std::path::PathBuf;
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+use super::SudoRequest;
+
+/// Builds a SudoRequest from Rust without spelling out every optional field:
+///
+/// ```
+/// use std::time::Duration;
+/// use term::sudo::SudoRequest;
+///
+/// let request = SudoRequest::builder("systemctl")
+///     .args(["restart", "nginx"])
+///     .timeout(Duration::from_secs(30))
+///     .build();
+/// assert_eq!(request.args, ["restart", "nginx"]);
+/// assert_eq!(request.timeout_secs, Some(30));
+/// ```
+#[derive(Debug)]
+pub struct SudoRequestBuilder {
+    request: SudoRequest,
+}
+
+impl SudoRequest {
+    pub fn builder(command: impl Into<String>) -> SudoRequestBuilder {
+        SudoRequestBuilder {
+            request: SudoRequest {
+                command: command.into(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl SudoRequestBuilder {
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.request.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.request.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.request.password = Some(Zeroizing::new(password.into()));
+        self
+    }
+
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.request
+            .env
+            .get_or_insert_with(Default::default)
+            .insert(name.into(), value.into());
+        self
+    }
+
+    pub fn cwd(mut self, cwd: impl Into<PathBuf>) -> Self {
+        self.request.cwd = Some(cwd.into());
+        self
+    }
+
+    // Rounded up to whole seconds, so a sub-second timeout still applies
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+        self.request.timeout_secs = Some(secs);
+        self
+    }
+
+    pub fn run_as(mut self, user: impl Into<String>) -> Self {
+        self.request.run_as = Some(user.into());
+        self
+    }
+
+    pub fn stdin(mut self, input: impl Into<String>) -> Self {
+        self.request.stdin = Some(input.into());
+        self
+    }
+
+    pub fn build(self) -> SudoRequest {
+        self.request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn builds_the_same_request_as_writing_it_out() {
+        let built = SudoRequest::builder("apt")
+            .arg("install")
+            .args(["-y", "curl"])
+            .password("hunter2")
+            .env("DEBIAN_FRONTEND", "noninteractive")
+            .cwd("/tmp")
+            .timeout(Duration::from_secs(90))
+            .run_as("root")
+            .stdin("yes\n")
+            .build();
+        let expected = SudoRequest {
+            command: "apt".to_string(),
+            args: vec!["install".to_string(), "-y".to_string(), "curl".to_string()],
+            password: Some(Zeroizing::new("hunter2".to_string())),
+            env: Some(HashMap::from([(
+                "DEBIAN_FRONTEND".to_string(),
+                "noninteractive".to_string(),
+            )])),
+            cwd: Some(PathBuf::from("/tmp")),
+            timeout_secs: Some(90),
+            run_as: Some("root".to_string()),
+            stdin: Some("yes\n".to_string()),
+            ..Default::default()
+        };
+
+        // SudoRequest has no PartialEq, its serialized form covers every field
+        assert_eq!(
+            serde_json::to_value(&built).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn rounds_a_sub_second_timeout_up() {
+        let request = SudoRequest::builder("true")
+            .timeout(Duration::from_millis(1500))
+            .build();
+        assert_eq!(request.timeout_secs, Some(2));
+
+        let request = SudoRequest::builder("true")
+            .timeout(Duration::from_millis(1))
+            .build();
+        assert_eq!(request.timeout_secs, Some(1));
+    }
+
+    #[test]
+    fn leaves_unset_fields_at_their_defaults() {
+        let request = SudoRequest::builder("ls").build();
+        assert_eq!(request.command, "ls");
+        assert!(request.args.is_empty());
+        assert!(request.password.is_none());
+        assert!(request.env.is_none());
+        assert!(request.capture_stdout && request.capture_stderr);
+    }
+}
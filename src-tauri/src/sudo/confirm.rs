@@ -0,0 +1,109 @@
+// src-tauri/src/sudo/confirm.rs
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How long the frontend has to echo a token back
+const TOKEN_LIFETIME: Duration = Duration::from_secs(120);
+
+// Tokens for commands in SudoConfig.require_confirmation_for. Each one is
+// tied to the exact command it was issued for, expires after
+// TOKEN_LIFETIME and works once, so a script replaying requests can't get
+// one of those commands through by accident.
+#[derive(Default, Clone)]
+pub struct Confirmations {
+    // Token to the command it confirms and when it was issued
+    issued: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+impl Confirmations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A fresh random token for `subject`, any string identifying the command
+    pub fn issue(&self, subject: &str) -> Result<String, String> {
+        let mut bytes = [0u8; 16];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|e| format!("Failed to generate confirmation token: {}", e))?;
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        let mut issued = self
+            .issued
+            .lock()
+            .map_err(|_| "Confirmation registry is unavailable".to_string())?;
+        let now = Instant::now();
+        issued.retain(|_, (_, at)| now.saturating_duration_since(*at) < TOKEN_LIFETIME);
+        issued.insert(token.clone(), (subject.to_string(), now));
+        Ok(token)
+    }
+
+    // Spends the tokens for a set of (token, subject) claims, but only when
+    // every one of them is good; otherwise nothing is spent and the index of
+    // the first bad claim comes back
+    pub fn redeem(&self, claims: &[(Option<&str>, &str)]) -> Result<(), usize> {
+        let mut issued = self.issued.lock().map_err(|_| 0usize)?;
+        let now = Instant::now();
+        let bad = claims.iter().position(|(token, subject)| {
+            !token
+                .and_then(|token| issued.get(token))
+                .is_some_and(|(issued_for, at)| {
+                    issued_for == subject && now.saturating_duration_since(*at) < TOKEN_LIFETIME
+                })
+        });
+        if let Some(bad) = bad {
+            return Err(bad);
+        }
+
+        for token in claims.iter().filter_map(|(token, _)| *token) {
+            issued.remove(token);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_token_works_once_for_its_subject() {
+        let confirmations = Confirmations::new();
+        let token = confirmations.issue("reboot").unwrap();
+
+        assert_eq!(confirmations.redeem(&[(Some(&token), "shutdown")]), Err(0));
+        assert_eq!(confirmations.redeem(&[(Some(&token), "reboot")]), Ok(()));
+        assert_eq!(confirmations.redeem(&[(Some(&token), "reboot")]), Err(0));
+    }
+
+    #[test]
+    fn nothing_is_spent_when_a_claim_is_bad() {
+        let confirmations = Confirmations::new();
+        let first = confirmations.issue("a").unwrap();
+        let second = confirmations.issue("b").unwrap();
+        assert_ne!(first, second);
+
+        assert_eq!(confirmations.redeem(&[(Some(&first), "a"), (None, "b")]), Err(1));
+        assert_eq!(
+            confirmations.redeem(&[(Some(&first), "a"), (Some(&second), "b")]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn tokens_expire() {
+        let confirmations = Confirmations::new();
+        let token = confirmations.issue("reboot").unwrap();
+        let Some(issued_at) = Instant::now().checked_sub(TOKEN_LIFETIME) else {
+            return;
+        };
+        if let Some(entry) = confirmations.issued.lock().unwrap().get_mut(&token) {
+            entry.1 = issued_at;
+        }
+
+        assert_eq!(confirmations.redeem(&[(Some(&token), "reboot")]), Err(0));
+        // Issuing sweeps out the expired one
+        confirmations.issue("other").unwrap();
+        assert!(!confirmations.issued.lock().unwrap().contains_key(&token));
+    }
+}
@@ -0,0 +1,112 @@
+// src-tauri/src/sudo/keystore.rs
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+// Somewhere to keep the user's password between sessions, used when
+// SudoConfig.use_keyring is set. Nothing read from here is ever logged or
+// handed back to the frontend; it only goes to the backend's password check.
+pub trait PasswordStore: Send + Sync {
+    // None when no password has been stored
+    fn get(&self) -> Result<Option<Zeroizing<String>>, String>;
+    fn set(&self, password: &str) -> Result<(), String>;
+    // Succeeds when there was nothing to delete
+    fn delete(&self) -> Result<(), String>;
+}
+
+// What the Tauri commands get from managed state
+pub type SharedPasswordStore = Arc<dyn PasswordStore>;
+
+// The OS keyring: Keychain on macOS, Credential Manager on Windows, and the
+// Secret Service (GNOME Keyring, KWallet) elsewhere
+pub struct KeyringStore {
+    service: String,
+    user: String,
+}
+
+impl KeyringStore {
+    pub fn new(service: impl Into<String>, user: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+            user: user.into(),
+        }
+    }
+
+    fn entry(&self) -> Result<keyring::Entry, String> {
+        keyring::Entry::new(&self.service, &self.user)
+            .map_err(|e| format!("Failed to open keyring entry: {}", e))
+    }
+}
+
+impl PasswordStore for KeyringStore {
+    fn get(&self) -> Result<Option<Zeroizing<String>>, String> {
+        match self.entry()?.get_password() {
+            Ok(password) => Ok(Some(Zeroizing::new(password))),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(format!("Failed to read keyring: {}", e)),
+        }
+    }
+
+    fn set(&self, password: &str) -> Result<(), String> {
+        self.entry()?
+            .set_password(password)
+            .map_err(|e| format!("Failed to write keyring: {}", e))
+    }
+
+    fn delete(&self) -> Result<(), String> {
+        match self.entry()?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(format!("Failed to clear keyring: {}", e)),
+        }
+    }
+}
+
+// Keyring entry name for the user running the app
+pub fn current_user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "default".to_string())
+}
+
+// Stand-in for KeyringStore that keeps the password in memory. Test-only.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockStore {
+    password: Mutex<Option<Zeroizing<String>>>,
+}
+
+#[cfg(test)]
+impl MockStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(test)]
+impl PasswordStore for MockStore {
+    fn get(&self) -> Result<Option<Zeroizing<String>>, String> {
+        self.password
+            .lock()
+            .map(|password| password.clone())
+            .map_err(|_| "Mock store is unavailable".to_string())
+    }
+
+    fn set(&self, password: &str) -> Result<(), String> {
+        let mut stored = self
+            .password
+            .lock()
+            .map_err(|_| "Mock store is unavailable".to_string())?;
+        *stored = Some(Zeroizing::new(password.to_string()));
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), String> {
+        let mut stored = self
+            .password
+            .lock()
+            .map_err(|_| "Mock store is unavailable".to_string())?;
+        *stored = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,5762 @@
+// src-tauri/src/sudo/mod.rs
+// Unsafe code is kept to the two places that can't do without it, each
+// allowed explicitly: the Win32 calls in uac, and spawn_in_new_session.
+#![deny(unsafe_code)]
+
+#[cfg(unix)]
+pub mod askpass;
+pub mod audit;
+pub mod backend;
+pub mod builder;
+pub mod confirm;
+pub mod keystore;
+pub mod policy;
+pub mod progress;
+pub mod prompt;
+pub mod ratelimit;
+pub mod runner;
+pub mod sudoers;
+pub mod systemctl;
+#[allow(unsafe_code)]
+pub mod uac;
+
+pub use backend::{ActiveBackend, PrivilegeBackend};
+pub use builder::SudoRequestBuilder;
+pub use confirm::Confirmations;
+pub use keystore::{KeyringStore, PasswordStore, SharedPasswordStore};
+#[cfg(test)]
+pub use keystore::MockStore;
+pub use progress::ProgressKind;
+pub use prompt::PendingPrompts;
+pub use ratelimit::RateLimiter;
+#[cfg(test)]
+pub use runner::MockRunner;
+pub use runner::{CommandRunner, SharedRunner, SystemRunner};
+pub use sudoers::{SudoVersion, ValidationResult};
+pub use systemctl::UnitStatus;
+
+use std::collections::HashMap;
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::watch;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use zeroize::Zeroizing;
+use serde::{Deserialize, Serialize};
+
+use backend::Verification;
+
+#[derive(Debug, Clone)]
+pub struct AuthToken {
+    timestamp: Instant,
+    // None for a timeout too long for Instant to reach, i.e. never
+    expires_at: Option<Instant>,
+    // When the password was entered. Refreshes leave it alone, so
+    // `max_session` caps the session however busy it's kept.
+    session_start: Instant,
+    max_session: Option<Duration>,
+    user_id: u32,
+    // Why the session was started, from SudoRequest.reason
+    reason: Option<String>,
+}
+
+impl AuthToken {
+    fn new(
+        user_id: u32,
+        now: Instant,
+        timeout_minutes: u64,
+        max_session_secs: Option<u64>,
+        reason: Option<String>,
+    ) -> Self {
+        let mut token = Self {
+            timestamp: now,
+            expires_at: None,
+            session_start: now,
+            max_session: max_session_secs.map(Duration::from_secs),
+            user_id,
+            reason,
+        };
+        token.renew(now, timeout_minutes);
+        token
+    }
+
+    // Restarts the timeout from `now`, but never past the session's cap
+    fn renew(&mut self, now: Instant, timeout_minutes: u64) {
+        let idle = now.checked_add(cache_timeout(timeout_minutes));
+        let cap = self
+            .max_session
+            .and_then(|max_session| self.session_start.checked_add(max_session));
+        self.timestamp = now;
+        self.expires_at = match (idle, cap) {
+            (Some(idle), Some(cap)) => Some(idle.min(cap)),
+            (idle, cap) => idle.or(cap),
+        };
+    }
+
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires_at.map_or(true, |expires_at| now < expires_at)
+    }
+}
+
+// The task started by start_sudo_keepalive, if there is one
+#[derive(Default, Clone)]
+pub struct SudoKeepalive {
+    task: Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>,
+}
+
+impl SudoKeepalive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.task
+            .lock()
+            .is_ok_and(|task| task.as_ref().is_some_and(|task| !task.is_finished()))
+    }
+
+    // Swaps in a new task (or none), stopping whatever ran before
+    fn replace(&self, task: Option<tokio::task::JoinHandle<()>>) {
+        if let Ok(mut current) = self.task.lock() {
+            if let Some(previous) = std::mem::replace(&mut *current, task) {
+                previous.abort();
+            }
+        }
+    }
+}
+
+// Every run in progress, so kill_all can reach them on exit. Those given a
+// request id can also be stopped one at a time, see cancel_sudo.
+#[derive(Default, Clone)]
+pub struct RunningCommands {
+    commands: Arc<Mutex<HashMap<RunKey, RunEntry>>>,
+    next_anonymous: Arc<AtomicU64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum RunKey {
+    Named(String),
+    Anonymous(u64),
+}
+
+struct RunEntry {
+    cancel: watch::Sender<bool>,
+    // The spawned process, which leads its own process group
+    pid: Option<u32>,
+}
+
+impl RunningCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Ids must be unique among running commands, or a cancel couldn't tell
+    // them apart
+    fn register(&self, request_id: Option<&str>) -> Result<Running, String> {
+        let mut commands = self
+            .commands
+            .lock()
+            .map_err(|_| "Running command registry is unavailable".to_string())?;
+        let key = match request_id {
+            Some(request_id) => RunKey::Named(request_id.to_string()),
+            None => RunKey::Anonymous(self.next_anonymous.fetch_add(1, Ordering::Relaxed)),
+        };
+        if let (RunKey::Named(request_id), true) = (&key, commands.contains_key(&key)) {
+            return Err(format!("Request {} is already running", request_id));
+        }
+
+        let (cancel, cancelled) = watch::channel(false);
+        commands.insert(key.clone(), RunEntry { cancel, pid: None });
+        Ok(Running {
+            commands: self.clone(),
+            key,
+            cancelled,
+        })
+    }
+
+    // Ids of the running commands that were given one, sorted. Runs drop
+    // out as they finish, see Running.
+    pub fn request_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = match self.commands.lock() {
+            Ok(commands) => commands
+                .keys()
+                .filter_map(|key| match key {
+                    RunKey::Named(request_id) => Some(request_id.clone()),
+                    RunKey::Anonymous(_) => None,
+                })
+                .collect(),
+            Err(_) => return Vec::new(),
+        };
+        ids.sort();
+        ids
+    }
+
+    // The process running under that id, once it's been spawned
+    pub fn pid(&self, request_id: &str) -> Option<u32> {
+        let key = RunKey::Named(request_id.to_string());
+        self.commands.lock().ok()?.get(&key)?.pid
+    }
+
+    // False when nothing is running under that id
+    pub fn cancel(&self, request_id: &str) -> bool {
+        let key = RunKey::Named(request_id.to_string());
+        self.commands
+            .lock()
+            .ok()
+            .and_then(|commands| commands.get(&key).map(|entry| entry.cancel.send(true).is_ok()))
+            .unwrap_or(false)
+    }
+
+    // For app exit, when there's no runtime left to cancel runs the usual
+    // way: signals every process group straight away, like terminate does,
+    // SIGTERM first so sudo can pass it on, then SIGKILL after a grace
+    // period. Calling it again once they're gone does nothing.
+    pub fn kill_all(&self) {
+        let pids: Vec<u32> = match self.commands.lock() {
+            Ok(commands) => commands
+                .values()
+                .filter_map(|entry| {
+                    let _ = entry.cancel.send(true);
+                    entry.pid
+                })
+                .collect(),
+            Err(_) => return,
+        };
+
+        #[cfg(unix)]
+        {
+            use nix::sys::signal::{killpg, Signal};
+            use nix::unistd::Pid;
+
+            let groups: Vec<Pid> = pids.into_iter().map(|pid| Pid::from_raw(pid as i32)).collect();
+            let mut alive: Vec<Pid> = groups
+                .into_iter()
+                .filter(|&pgid| killpg(pgid, Signal::SIGTERM).is_ok())
+                .collect();
+            let deadline = Instant::now() + Duration::from_secs(2);
+            while !alive.is_empty() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(50));
+                alive.retain(|&pgid| killpg(pgid, None).is_ok());
+            }
+            for pgid in alive {
+                let _ = killpg(pgid, Signal::SIGKILL);
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = pids;
+    }
+}
+
+// A registered run. Its entry goes away when this is dropped, however the
+// run ended.
+struct Running {
+    commands: RunningCommands,
+    key: RunKey,
+    cancelled: watch::Receiver<bool>,
+}
+
+impl Running {
+    // Records the spawned process for kill_all
+    fn track(&self, pid: Option<u32>) {
+        if let Ok(mut commands) = self.commands.commands.lock() {
+            if let Some(entry) = commands.get_mut(&self.key) {
+                entry.pid = pid;
+            }
+        }
+    }
+
+    // Resolves once cancel_sudo is called for this run
+    async fn cancelled(&mut self) {
+        if self.cancelled.wait_for(|cancelled| *cancelled).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Ok(mut commands) = self.commands.commands.lock() {
+            commands.remove(&self.key);
+        }
+    }
+}
+
+type SharedResult = watch::Receiver<Option<Result<SudoResponse, String>>>;
+
+// fast_sudo calls still running, so an identical second call (say from a
+// double-click) waits for the first one's response rather than spawning
+// another process
+#[derive(Default, Clone)]
+pub struct InFlightRequests {
+    requests: Arc<Mutex<HashMap<InFlightKey, SharedResult>>>,
+}
+
+// What makes two requests the same: everything that changes how the command
+// runs or what comes back from it. Whether a password came along matters
+// too: a call that brings one shouldn't get a "password required" back from
+// one that didn't. A request_id is kept so each named run can be cancelled.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct InFlightKey {
+    user_id: u32,
+    run_as: Option<String>,
+    // Canonical, so `apt` and `/usr/bin/apt` are the same
+    command: String,
+    args: Vec<String>,
+    with_password: bool,
+    // Sorted, HashMap has no Hash of its own
+    env: Vec<(String, String)>,
+    preserve_env: Vec<String>,
+    cwd: Option<PathBuf>,
+    timeout_secs: Option<u64>,
+    stdin: Option<String>,
+    merge_streams: bool,
+    login_shell: bool,
+    max_output_bytes: Option<usize>,
+    raw_output: bool,
+    request_id: Option<String>,
+    capture_env: bool,
+    use_pty: bool,
+    askpass: Option<PathBuf>,
+    output_encoding: Option<String>,
+    capture_stdout: bool,
+    capture_stderr: bool,
+}
+
+impl InFlightKey {
+    fn new(request: &SudoRequest) -> Self {
+        let mut env: Vec<(String, String)> = request
+            .env
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        env.sort();
+        Self {
+            user_id: get_current_user_id(),
+            run_as: request.run_as.clone(),
+            command: policy::display_command(&request.command, request.cwd.as_deref()),
+            args: request.args.clone(),
+            with_password: request.password.is_some(),
+            env,
+            preserve_env: request.preserve_env.clone(),
+            cwd: request.cwd.clone(),
+            timeout_secs: request.timeout_secs,
+            stdin: request.stdin.clone(),
+            merge_streams: request.merge_streams,
+            login_shell: request.login_shell,
+            max_output_bytes: request.max_output_bytes,
+            raw_output: request.raw_output,
+            request_id: request.request_id.clone(),
+            capture_env: request.capture_env,
+            use_pty: request.use_pty,
+            askpass: request.askpass.clone(),
+            output_encoding: request.output_encoding.clone(),
+            capture_stdout: request.capture_stdout,
+            capture_stderr: request.capture_stderr,
+        }
+    }
+}
+
+impl InFlightRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs `run` unless an identical request is already running, in which
+    // case that one's result is shared instead
+    async fn coalesce(
+        &self,
+        key: InFlightKey,
+        run: impl std::future::Future<Output = Result<SudoResponse, String>>,
+    ) -> Result<SudoResponse, String> {
+        let leader = match self.requests.lock() {
+            Ok(mut requests) => match requests.get(&key) {
+                Some(result) => Err(result.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    requests.insert(key.clone(), receiver);
+                    Ok(Some(sender))
+                }
+            },
+            Err(_) => Ok(None),
+        };
+
+        let sender = match leader {
+            Ok(sender) => sender,
+            Err(mut result) => {
+                let shared = result
+                    .wait_for(Option::is_some)
+                    .await
+                    .ok()
+                    .and_then(|result| result.clone());
+                if let Some(result) = shared {
+                    return result;
+                }
+                // The first call went away without an answer, run it after all
+                return run.await;
+            }
+        };
+
+        let _entry = InFlightEntry {
+            requests: self.clone(),
+            key,
+        };
+        let result = run.await;
+        if let Some(sender) = sender {
+            let _ = sender.send(Some(result.clone()));
+        }
+        result
+    }
+}
+
+// Removes a leader's entry once it's done, or if its call is dropped midway
+struct InFlightEntry {
+    requests: InFlightRequests,
+    key: InFlightKey,
+}
+
+impl Drop for InFlightEntry {
+    fn drop(&mut self) {
+        if let Ok(mut requests) = self.requests.requests.lock() {
+            requests.remove(&self.key);
+        }
+    }
+}
+
+// Tokens are kept per backend and target user as well, so authenticating
+// sudo for root covers neither doas nor a `-u www-data` run
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub backend: PrivilegeBackend,
+    pub user_id: u32,
+    // None for root, the default target
+    pub run_as: Option<String>,
+}
+
+impl CacheKey {
+    pub fn new(backend: PrivilegeBackend, user_id: u32, run_as: Option<&str>) -> Self {
+        Self {
+            backend,
+            user_id,
+            run_as: run_as.filter(|user| *user != "root").map(str::to_string),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct SudoCache {
+    pub tokens: Arc<Mutex<HashMap<CacheKey, AuthToken>>>,
+    // Consecutive failed password attempts and when the last one happened
+    pub failures: Arc<Mutex<HashMap<u32, (u32, Instant)>>>,
+    // Password prompts triggered by an expired token since the user last
+    // authenticated, and when the last one went out
+    pub reauth_prompts: Arc<Mutex<HashMap<u32, (u32, Instant)>>>,
+    shut_down: Arc<AtomicBool>,
+    // Dropping it stops the thread start_sweeper started
+    sweeper: Arc<Mutex<Option<std::sync::mpsc::Sender<()>>>>,
+}
+
+// Cooldown after the first expiry-triggered prompt, doubled for each one
+// after that up to REAUTH_BACKOFF_MAX
+const REAUTH_BACKOFF: Duration = Duration::from_secs(1);
+const REAUTH_BACKOFF_MAX: Duration = Duration::from_secs(32);
+
+#[derive(Debug, Clone)]
+pub struct SudoConfig {
+    // Mirrors sudoers' `timestamp_timeout`: 0 disables caching entirely and
+    // very large values effectively never expire.
+    pub timeout_minutes: u64,
+    // Failed attempts before password checks are refused, 0 disables lockout
+    pub max_failed_attempts: u32,
+    pub lockout_secs: u64,
+    // Where executed commands are recorded, see audit::default_log_path
+    pub audit_log: Option<PathBuf>,
+    // Size at which the audit log is rotated, 0 for no cap
+    pub audit_max_bytes: u64,
+    // Chain every audit entry with an HMAC under this key, so edits to the
+    // log show up in verify_audit_log. None writes plain entries.
+    pub audit_signing: Option<audit::AuditKey>,
+    // Commands allowed to run elevated, by name or path. None allows anything.
+    pub allowlist: Option<Vec<String>>,
+    // Sees every command and its args before they run, returning false to
+    // refuse them as NotPermitted. policy::deny_shells is one to start from.
+    pub arg_validator: Option<fn(&str, &[String]) -> bool>,
+    // Restart the timeout whenever the cache is used, like sudo does with its
+    // timestamp, rather than counting from the last password entry
+    pub sliding_expiry: bool,
+    // How the password reaches sudo, see PasswordMode
+    pub password_mode: PasswordMode,
+    // How long a password check may take before sudo is killed, 0 waits
+    // forever. PAM can block indefinitely, e.g. on an unplugged token.
+    pub verify_timeout_secs: u64,
+    // Most verbose level init_logging lets through
+    pub log_level: tracing::Level,
+    // Requests without a password use the one saved with store_sudo_password
+    pub use_keyring: bool,
+    // Keyring service the password is saved under, one entry per login name
+    pub keyring_service: String,
+    // Commands that ask for the password every time, cache or no cache.
+    // Matched like the allowlist, so `rm` covers /bin/rm as well.
+    pub always_prompt: Vec<String>,
+    // Sustained executions allowed per second for each uid, 0 for no limit
+    pub rate_limit_per_sec: u32,
+    // Executions a uid may start at once before the rate applies
+    pub rate_limit_burst: u32,
+    // Commands that only run with a token from a ConfirmationRequired
+    // response, matched like the allowlist. Enforced by the Tauri commands.
+    pub require_confirmation_for: Vec<String>,
+    // Hard cap on a session, counted from the password entry. Unlike the
+    // timeout, activity and sliding_expiry don't extend it. None for no cap.
+    pub max_session_secs: Option<u64>,
+    // Questions an interactive run may ask, its password included, before
+    // it's given up on
+    pub max_prompts: u32,
+    // Check a password a request brings even while the cache is valid, so a
+    // wrong one fails before anything runs instead of being ignored
+    pub revalidate_when_password_present: bool,
+    // Friendlier descriptions to prompt with, see policy::label_for
+    pub command_labels: HashMap<String, String>,
+    // How often expired tokens are swept out, see SudoCache::start_sweeper.
+    // 0 leaves them to be dropped as requests run into them.
+    pub sweep_interval_secs: u64,
+    // Further attempts at spawning after a transient failure such as EAGAIN
+    pub spawn_retries: u32,
+    // How long a UAC run may take, consent dialog included, when the request
+    // sets no timeout_secs of its own. None waits for it however long.
+    // direct_privilege_escalation holds pkexec's polkit dialog to it too.
+    pub uac_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordMode {
+    // Written to `sudo -S` on stdin
+    #[default]
+    Stdin,
+    // Served to `sudo -A` by an app-controlled SUDO_ASKPASS helper, see
+    // askpass.rs. Also works with PAM setups that insist on a prompt
+    // rather than a line on stdin.
+    Askpass,
+}
+
+impl Default for SudoConfig {
+    fn default() -> Self {
+        Self {
+            timeout_minutes: 15,
+            max_failed_attempts: 3,
+            lockout_secs: 60,
+            audit_log: None,
+            audit_max_bytes: 1024 * 1024,
+            audit_signing: None,
+            allowlist: None,
+            arg_validator: None,
+            sliding_expiry: true,
+            password_mode: PasswordMode::Stdin,
+            verify_timeout_secs: 30,
+            log_level: tracing::Level::INFO,
+            use_keyring: false,
+            keyring_service: "term-sudo".to_string(),
+            always_prompt: Vec::new(),
+            rate_limit_per_sec: 10,
+            rate_limit_burst: 20,
+            max_prompts: 3,
+            require_confirmation_for: Vec::new(),
+            max_session_secs: None,
+            revalidate_when_password_present: false,
+            command_labels: HashMap::new(),
+            sweep_interval_secs: 60,
+            spawn_retries: 3,
+            uac_timeout_secs: Some(600),
+        }
+    }
+}
+
+fn cache_timeout(timeout_minutes: u64) -> Duration {
+    Duration::from_secs(timeout_minutes.saturating_mul(60))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SudoRequest {
+    pub command: String,
+    pub args: Vec<String>,
+    // Wiped from memory on drop, and taken out of the request as soon as it's verified
+    pub password: Option<Zeroizing<String>>,
+    // Set on the sudo process itself. Sudo resets the environment by default,
+    // so these names are also passed through --preserve-env; anything not in
+    // that list never reaches the elevated command.
+    pub env: Option<HashMap<String, String>>,
+    // Extra inherited variables to keep through sudo's env_reset
+    #[serde(default)]
+    pub preserve_env: Vec<String>,
+    // Directory the elevated command runs in, defaults to the app's cwd
+    pub cwd: Option<PathBuf>,
+    // Kill the command (and anything it spawned) if it runs longer than this
+    pub timeout_secs: Option<u64>,
+    // Piped to the command's stdin, which is then closed. This is unrelated to
+    // the password: that's fed to a separate `sudo -S -v` beforehand, and the
+    // command itself runs with -n so sudo never reads a password from here.
+    pub stdin: Option<String>,
+    // Report the command that would run in `planned_command` without running it
+    #[serde(default)]
+    pub dry_run: bool,
+    // Send stderr into `output` along with stdout instead of into `error`
+    #[serde(default)]
+    pub merge_streams: bool,
+    // Target user for `sudo -u`, root when unset
+    pub run_as: Option<String>,
+    // Run through the target user's login shell with its environment, like
+    // `sudo -i`. Ignored by backends without one.
+    #[serde(default)]
+    pub login_shell: bool,
+    // Cap on how much of stdout and of stderr is kept, see
+    // SudoResponse.truncated. The command still runs to completion.
+    pub max_output_bytes: Option<usize>,
+    // Also return stdout untouched in `output_bytes`, for binary output that
+    // the lossy UTF-8 `output` would mangle
+    #[serde(default)]
+    pub raw_output: bool,
+    // fast_sudo_stream only: turn this package manager's progress output into
+    // `sudo://progress` events instead of plain lines
+    pub progress_parser: Option<ProgressKind>,
+    // Lets cancel_sudo stop this run. fast_sudo_stream takes its id separately.
+    pub request_id: Option<String>,
+    // Also report the environment the command ran with in `effective_env`
+    #[serde(default)]
+    pub capture_env: bool,
+    // fast_sudo_stream only: let sudo ask for itself and relay each question
+    // as a `sudo://prompt` event, see run_interactive. Takes no stdin.
+    #[serde(default)]
+    pub interactive: bool,
+    // fast_sudo only, Unix only: run the command on a pseudo-terminal so it
+    // sees a TTY, see attach_pty. Its output comes back merged in `output`,
+    // with the terminal's \r\n line endings and any colors left in.
+    #[serde(default)]
+    pub use_pty: bool,
+    // Echoed back from a ConfirmationRequired response, see
+    // SudoConfig.require_confirmation_for
+    pub confirmation_token: Option<String>,
+    // Sudo only: a helper of the caller's own for `sudo -A` to ask the user
+    // with. The app then neither needs nor checks a password.
+    pub askpass: Option<PathBuf>,
+    // fast_sudo only: the codeset the command writes in, as a WHATWG label
+    // like "latin1" or "shift_jis". Output and stderr are transcoded from it
+    // to UTF-8; None decodes them as UTF-8, lossily.
+    pub output_encoding: Option<String>,
+    // Why the session a password starts is wanted, e.g. "package update",
+    // shown in list_sudo_sessions. It never reaches the command.
+    pub reason: Option<String>,
+    // Whether stdout and stderr are kept. A stream that isn't goes to
+    // /dev/null rather than being read, and sudo's own complaints go with
+    // stderr. Ignored with use_pty.
+    #[serde(default = "default_true")]
+    pub capture_stdout: bool,
+    #[serde(default = "default_true")]
+    pub capture_stderr: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for SudoRequest {
+    fn default() -> Self {
+        Self {
+            command: String::new(),
+            args: Vec::new(),
+            password: None,
+            env: None,
+            preserve_env: Vec::new(),
+            cwd: None,
+            timeout_secs: None,
+            stdin: None,
+            dry_run: false,
+            merge_streams: false,
+            run_as: None,
+            login_shell: false,
+            max_output_bytes: None,
+            raw_output: false,
+            progress_parser: None,
+            request_id: None,
+            capture_env: false,
+            interactive: false,
+            use_pty: false,
+            confirmation_token: None,
+            askpass: None,
+            output_encoding: None,
+            reason: None,
+            capture_stdout: true,
+            capture_stderr: true,
+        }
+    }
+}
+
+// Written out by hand so the password never ends up in a log line
+impl std::fmt::Debug for SudoRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SudoRequest")
+            .field("command", &self.command)
+            .field("args", &self.args)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("env", &self.env)
+            .field("preserve_env", &self.preserve_env)
+            .field("cwd", &self.cwd)
+            .field("timeout_secs", &self.timeout_secs)
+            .field("stdin", &self.stdin)
+            .field("dry_run", &self.dry_run)
+            .field("merge_streams", &self.merge_streams)
+            .field("run_as", &self.run_as)
+            .field("login_shell", &self.login_shell)
+            .field("max_output_bytes", &self.max_output_bytes)
+            .field("raw_output", &self.raw_output)
+            .field("progress_parser", &self.progress_parser)
+            .field("request_id", &self.request_id)
+            .field("capture_env", &self.capture_env)
+            .field("interactive", &self.interactive)
+            .field("use_pty", &self.use_pty)
+            .field("confirmation_token", &self.confirmation_token)
+            .field("askpass", &self.askpass)
+            .field("output_encoding", &self.output_encoding)
+            .field("reason", &self.reason)
+            .field("capture_stdout", &self.capture_stdout)
+            .field("capture_stderr", &self.capture_stderr)
+            .finish()
+    }
+}
+
+// Machine-readable reason behind `SudoResponse.error`, so the frontend doesn't
+// have to match on the message. A command that simply exits non-zero has none.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", content = "detail", rename_all = "snake_case")]
+pub enum SudoError {
+    PasswordRequired,
+    InvalidPassword,
+    LockedOut,
+    NotPermitted,
+    Timeout,
+    SpawnFailed(String),
+    Backend(String),
+    UnknownUser(String),
+    AccountLocked,
+    PasswordExpired,
+    Cancelled,
+    // Another request whose token expired is already asking for the password
+    ReauthPending,
+    // Over SudoConfig.rate_limit_per_sec, nothing was spawned
+    RateLimited,
+    // An interactive run asked more than SudoConfig.max_prompts questions
+    TooManyPrompts,
+    // The backend itself (sudo, doas, pkexec) isn't installed
+    NoSudoBinary,
+    // The command needs confirming. Carries the token to send back in
+    // SudoRequest.confirmation_token to run it.
+    ConfirmationRequired(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SudoResponse {
+    pub success: bool,
+    pub output: String,
+    pub error: Option<String>,
+    pub error_kind: Option<SudoError>,
+    pub cached: bool,
+    pub needs_password: bool,
+    pub exit_code: Option<i32>,
+    // Set instead of `exit_code` when the process was killed by a signal (Unix only)
+    pub signal: Option<i32>,
+    // What actually ran the command: "sudo", "doas", "pkexec", "runas" (UAC)
+    // or "direct" (the app already had root). Empty when nothing was executed.
+    pub method: String,
+    // Full argv a dry run would have spawned, env assignments first
+    pub planned_command: Option<Vec<String>>,
+    // Output went past max_output_bytes and was cut off
+    pub truncated: bool,
+    // Exact stdout bytes when the request set raw_output, base64-encoded
+    // (standard alphabet, padded) since JSON has no byte strings
+    #[serde(with = "base64_bytes", default)]
+    pub output_bytes: Option<Vec<u8>>,
+    // Everything the command wrote to stderr, set whether or not it succeeded.
+    // None when stderr was merged into `output` or the command never ran.
+    pub stderr: Option<String>,
+    // Wall time from spawning the command until it exited, 0 if it never ran
+    #[serde(default)]
+    pub duration_ms: u64,
+    // Time spent checking the password first, when that happened
+    pub auth_duration_ms: Option<u64>,
+    // With capture_env, the environment left after sudo's env_reset and
+    // secure_path. None if it couldn't be read, or the backend prompts
+    // on its own (pkexec, UAC).
+    pub effective_env: Option<HashMap<String, String>>,
+    // Full argv that was spawned, backend and flags included. Empty when
+    // nothing ran. The password never goes through argv.
+    #[serde(default)]
+    pub executed_command: Vec<String>,
+    // direct_privilege_escalation only: why `method` isn't the preferred
+    // mechanism, when it had to fall back
+    pub escalation_warning: Option<String>,
+    // With needs_password, what the command does in words, from
+    // SudoConfig.command_labels
+    pub friendly_label: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SudoStreamLine {
+    pub request_id: String,
+    pub line: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SudoStreamProgress {
+    pub request_id: String,
+    pub percent: u8,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SudoStreamPrompt {
+    pub request_id: String,
+    pub prompt: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SudoStreamExit {
+    pub request_id: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub cached: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SudoCacheStatus {
+    pub authenticated: bool,
+    pub remaining_secs: Option<u64>,
+}
+
+// What check_sudo_privileges found out about escalating right now
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivilegeStatus {
+    // Cached, or no password needed at all
+    Available,
+    NeedsPassword,
+    NotInSudoers,
+    NoSudoBinary,
+}
+
+// Each check sudo_self_test ran
+#[derive(Serialize, Debug)]
+pub struct SudoSelfTest {
+    pub backend: PrivilegeBackend,
+    // Found on PATH
+    pub installed: bool,
+    // Its version output names the backend expected. None for doas and UAC,
+    // which have no version flag.
+    pub identified: Option<bool>,
+    // First line of that output
+    pub version: Option<String>,
+    // Whether `-n true` ran. None when nothing was cached to try it with.
+    pub cached_run: Option<bool>,
+}
+
+// Everything sudo_diagnostics reports. Only state and counts, never a
+// password or anything derived from one.
+#[derive(Serialize, Debug)]
+pub struct SudoDiagnostics {
+    pub backend: PrivilegeBackend,
+    pub backend_installed: bool,
+    // As detected at startup, None if it wasn't or the backend isn't sudo
+    pub sudo_version: Option<String>,
+    pub timeout_minutes: u64,
+    pub sessions: Vec<SessionInfo>,
+    pub keepalive_running: bool,
+    // Failed password attempts per uid since each last succeeded
+    pub failed_attempts: HashMap<u32, u32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SessionInfo {
+    pub backend: PrivilegeBackend,
+    pub user_id: u32,
+    pub run_as: Option<String>,
+    pub age_secs: u64,
+    pub remaining_secs: u64,
+    pub reason: Option<String>,
+}
+
+impl SudoCache {
+    pub fn new() -> Self {
+        Self {
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            reauth_prompts: Arc::new(Mutex::new(HashMap::new())),
+            shut_down: Arc::new(AtomicBool::new(false)),
+            sweeper: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // A token carries its own expiry, so this is one lookup and a compare.
+    // An expired token is dropped when it's found here; nothing else is
+    // swept, clear_expired does that in the background.
+    pub fn is_authenticated(&self, key: &CacheKey) -> bool {
+        self.remaining(key).is_some()
+    }
+
+    // Time left before the user's token expires, None once it has. Expired
+    // tokens are dropped here too. Never-expiring ones report Duration::MAX.
+    pub fn remaining(&self, key: &CacheKey) -> Option<Duration> {
+        let now = Instant::now();
+        let mut tokens = self.tokens.lock().ok()?;
+        let token = tokens.get(key)?;
+        if !token.is_live(now) {
+            tokens.remove(key);
+            return None;
+        }
+        Some(token.expires_at.map_or(Duration::MAX, |expires_at| expires_at - now))
+    }
+
+    // Every user with a live token, dropping expired ones on the way
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let now = Instant::now();
+
+        let mut tokens = match self.tokens.lock() {
+            Ok(tokens) => tokens,
+            Err(_) => return Vec::new(),
+        };
+        tokens.retain(|_, token| token.is_live(now));
+
+        tokens
+            .iter()
+            .map(|(key, token)| SessionInfo {
+                backend: key.backend,
+                user_id: token.user_id,
+                run_as: key.run_as.clone(),
+                age_secs: now.saturating_duration_since(token.timestamp).as_secs(),
+                remaining_secs: token
+                    .expires_at
+                    .map_or(u64::MAX, |expires_at| (expires_at - now).as_secs()),
+                reason: token.reason.clone(),
+            })
+            .collect()
+    }
+
+    // Starts a new session, see SudoConfig.max_session_secs. The reason is
+    // only ever shown in sessions(), and goes when the token does.
+    pub fn authenticate(
+        &self,
+        key: &CacheKey,
+        timeout_minutes: u64,
+        max_session_secs: Option<u64>,
+        reason: Option<&str>,
+    ) {
+        let token = AuthToken::new(
+            key.user_id,
+            Instant::now(),
+            timeout_minutes,
+            max_session_secs,
+            reason.map(str::to_string),
+        );
+
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(key.clone(), token);
+        }
+    }
+
+    // Past half its lifetime. Instant may or may not count time spent
+    // suspended depending on the platform, so a token this old gets checked
+    // against the system timestamp before it's trusted.
+    pub fn is_stale(&self, key: &CacheKey) -> bool {
+        let now = Instant::now();
+        let token = match self.tokens.lock() {
+            Ok(tokens) => tokens.get(key).map(|token| (token.timestamp, token.expires_at)),
+            Err(_) => None,
+        };
+
+        token.is_some_and(|(timestamp, expires_at)| {
+            expires_at.is_some_and(|expires_at| now >= timestamp + (expires_at - timestamp) / 2)
+        })
+    }
+
+    pub fn remove(&self, key: &CacheKey) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.remove(key);
+        }
+    }
+
+    // Restarts an existing token's timeout, up to its session's cap. Expired
+    // tokens aren't revived.
+    pub fn refresh(&self, key: &CacheKey, timeout_minutes: u64) {
+        let now = Instant::now();
+
+        if let Ok(mut tokens) = self.tokens.lock() {
+            if let Some(token) = tokens.get_mut(key) {
+                if token.is_live(now) {
+                    token.renew(now, timeout_minutes);
+                }
+            }
+        }
+    }
+
+    // Cooldown left once the user has hit the failed-attempt limit. The count
+    // starts over after the cooldown passes.
+    pub fn lockout_remaining(
+        &self,
+        user_id: u32,
+        max_attempts: u32,
+        lockout: Duration,
+    ) -> Option<Duration> {
+        let mut failures = self.failures.lock().ok()?;
+        let (count, last_failure) = *failures.get(&user_id)?;
+
+        if max_attempts == 0 || count < max_attempts {
+            return None;
+        }
+
+        match lockout.checked_sub(last_failure.elapsed()) {
+            Some(remaining) if !remaining.is_zero() => Some(remaining),
+            _ => {
+                failures.remove(&user_id);
+                None
+            }
+        }
+    }
+
+    pub fn record_failure(&self, user_id: u32) {
+        if let Ok(mut failures) = self.failures.lock() {
+            let entry = failures.entry(user_id).or_insert((0, Instant::now()));
+            entry.0 += 1;
+            entry.1 = Instant::now();
+        }
+    }
+
+    // Failed attempts per uid, as counted towards a lockout
+    pub fn failure_counts(&self) -> HashMap<u32, u32> {
+        self.failures
+            .lock()
+            .map(|failures| failures.iter().map(|(uid, (count, _))| (*uid, *count)).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn clear_failures(&self, user_id: u32) {
+        if let Ok(mut failures) = self.failures.lock() {
+            failures.remove(&user_id);
+        }
+    }
+
+    // Claims the next password prompt after a token expired. While the
+    // previous prompt's cooldown is running this fails with the time left,
+    // so a burst of requests hitting the same expiry asks only once.
+    pub fn begin_reauth(&self, user_id: u32) -> Result<(), Duration> {
+        let mut prompts = match self.reauth_prompts.lock() {
+            Ok(prompts) => prompts,
+            Err(_) => return Ok(()),
+        };
+        let now = Instant::now();
+
+        if let Some((count, last)) = prompts.get_mut(&user_id) {
+            let cooldown = REAUTH_BACKOFF
+                .saturating_mul(1 << (*count - 1).min(5))
+                .min(REAUTH_BACKOFF_MAX);
+            let elapsed = now.saturating_duration_since(*last);
+            if elapsed < cooldown {
+                return Err(cooldown - elapsed);
+            }
+            *count += 1;
+            *last = now;
+        } else {
+            prompts.insert(user_id, (1, now));
+        }
+        Ok(())
+    }
+
+    pub fn clear_reauth(&self, user_id: u32) {
+        if let Ok(mut prompts) = self.reauth_prompts.lock() {
+            prompts.remove(&user_id);
+        }
+    }
+
+    // A full sweep, for the background task. Requests don't need it, they
+    // drop the one expired token they run into.
+    pub fn clear_expired(&self) {
+        let now = Instant::now();
+
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.retain(|_, token| token.is_live(now));
+        }
+    }
+
+    // Runs clear_expired every `interval` on a thread of its own, replacing
+    // any sweeper started before. A zero interval just stops the old one.
+    // shutdown stops it too.
+    pub fn start_sweeper(&self, interval: Duration) {
+        let Ok(mut sweeper) = self.sweeper.lock() else {
+            return;
+        };
+        *sweeper = None;
+        if interval.is_zero() {
+            return;
+        }
+
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let cache = self.clone();
+        std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                cache.clear_expired();
+            }
+        });
+        *sweeper = Some(stop);
+    }
+
+    // Drops every token the user holds, whatever the target user
+    pub fn clear_user(&self, user_id: u32) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.retain(|key, _| key.user_id != user_id);
+        }
+    }
+
+    pub fn clear_all(&self) {
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.clear();
+        }
+    }
+
+    // For app exit: forgets every token and the state around them, and drops
+    // the system timestamp so the next session starts from a password. Only
+    // the first call does anything. Running commands are RunningCommands'
+    // to stop, see kill_all.
+    pub fn shutdown(&self, runner: &dyn CommandRunner, backend: PrivilegeBackend) {
+        if self.shut_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        if let Ok(mut sweeper) = self.sweeper.lock() {
+            *sweeper = None;
+        }
+        self.clear_all();
+        if let Ok(mut failures) = self.failures.lock() {
+            failures.clear();
+        }
+        if let Ok(mut prompts) = self.reauth_prompts.lock() {
+            prompts.clear();
+        }
+        if let Some((program, args)) = backend.invalidate_command() {
+            let _ = runner.run(program, args, None);
+        }
+    }
+}
+
+// Splits an exit status into its exit code and, on Unix, the terminating signal
+fn exit_details(status: &ExitStatus) -> (Option<i32>, Option<i32>) {
+    #[cfg(unix)]
+    let signal = {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    };
+    #[cfg(not(unix))]
+    let signal = None;
+
+    (status.code(), signal)
+}
+
+// Sends tracing output, including the auth and execution spans, to stderr.
+// Passwords are never recorded. Does nothing if a subscriber is already set.
+pub fn init_logging(config: &SudoConfig) {
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(config.log_level)
+        .with_writer(std::io::stderr)
+        .try_init();
+}
+
+// Keeps passwords held in memory out of core dumps. Called once at startup;
+// failing only means a crash could dump more than we'd like.
+pub fn disable_core_dumps() {
+    #[cfg(unix)]
+    {
+        use nix::sys::resource::{setrlimit, Resource};
+        if let Err(e) = setrlimit(Resource::RLIMIT_CORE, 0, 0) {
+            tracing::warn!(error = %e, "failed to disable core dumps");
+        }
+    }
+}
+
+// getuid always succeeds, there's no error case to surface. Windows has no
+// numeric uid, and UAC never touches the cache anyway.
+fn get_current_user_id() -> u32 {
+    #[cfg(unix)]
+    {
+        nix::unistd::getuid().as_raw()
+    }
+    #[cfg(not(unix))]
+    {
+        0
+    }
+}
+
+fn is_elevated() -> bool {
+    #[cfg(unix)]
+    {
+        nix::unistd::geteuid().is_root()
+    }
+    #[cfg(not(unix))]
+    {
+        uac::is_elevated()
+    }
+}
+
+// Runs the backend's blocking plumbing on tokio's blocking pool. Password
+// checks and timestamp probes wait on sudo, and PAM can keep them waiting up
+// to verify_timeout_secs, which mustn't hold up an async worker.
+async fn blocking<T: Send + 'static>(
+    task: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, String> {
+    tokio::task::spawn_blocking(task)
+        .await
+        .map_err(|e| format!("Authentication failed: {}", e))
+}
+
+// sudo_timestamp_valid from async code
+async fn timestamp_still_valid(
+    runner: &SharedRunner,
+    backend: PrivilegeBackend,
+) -> Result<bool, String> {
+    let runner = runner.clone();
+    blocking(move || sudo_timestamp_valid(runner.as_ref(), backend)).await
+}
+
+// Non-interactive probe of the system sudo timestamp
+fn sudo_timestamp_valid(runner: &dyn CommandRunner, backend: PrivilegeBackend) -> bool {
+    runner
+        .run(backend.program(), &["-n", "true"], None)
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+// With no token in the cache, say right after the app restarted, a system
+// timestamp that's still valid is as good as a password. This reads system
+// state: it runs `sudo -n [-u user] true` and seeds the cache if that passes.
+fn seed_from_system(
+    runner: &dyn CommandRunner,
+    cache: &SudoCache,
+    key: &CacheKey,
+    config: &SudoConfig,
+    backend: PrivilegeBackend,
+) -> bool {
+    // There's no telling when a system timestamp's session began, so it
+    // can't be held to a cap
+    if config.timeout_minutes == 0 || config.max_session_secs.is_some() {
+        return false;
+    }
+
+    let valid = system_timestamp_valid(runner, backend, key);
+    if valid {
+        cache.authenticate(key, config.timeout_minutes, None, None);
+    }
+    valid
+}
+
+// A cached token is trusted as-is while it's fresh. Once stale, say after the
+// machine slept through most of the timeout, it only counts if sudo agrees,
+// and is dropped otherwise.
+fn confirm_cached(
+    runner: &dyn CommandRunner,
+    cache: &SudoCache,
+    key: &CacheKey,
+    backend: PrivilegeBackend,
+) -> bool {
+    if !cache.is_stale(key) {
+        return true;
+    }
+
+    let valid = system_timestamp_valid(runner, backend, key);
+    if !valid {
+        cache.remove(key);
+    }
+    valid
+}
+
+// `sudo -n [-u user] true`, for the key's target user
+fn system_timestamp_valid(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+    key: &CacheKey,
+) -> bool {
+    let mut args = vec!["-n"];
+    if let Some(user) = &key.run_as {
+        args.extend([backend.run_as_flag(), user.as_str()]);
+    }
+    args.push("true");
+
+    runner
+        .run(backend.program(), &args, None)
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+// How sudo answered a password. Locked and expired accounts are told apart
+// from a wrong password, since retyping it won't help there.
+enum Verdict {
+    Accepted,
+    Rejected,
+    Account(SudoError),
+    // sudo never answered, e.g. PAM waiting on a fingerprint reader
+    TimedOut,
+    // The backend has no check to run, see PrivilegeBackend::verify
+    Unsupported,
+}
+
+// PAM account problems as sudo reports them in the C locale, both the module
+// messages (pam_faillock, pam_unix) and sudo's own summaries of them
+fn account_problem(stderr: &str) -> Option<SudoError> {
+    let stderr = stderr.to_lowercase();
+    let locked = ["account is locked", "account locked", "is your account locked"];
+    let expired = ["password expired", "password has expired", "password is expired"];
+
+    if locked.iter().any(|message| stderr.contains(message)) {
+        Some(SudoError::AccountLocked)
+    } else if expired.iter().any(|message| stderr.contains(message)) {
+        Some(SudoError::PasswordExpired)
+    } else {
+        None
+    }
+}
+
+// Same, for a command's stderr: only the backend's own `sudo: ...` lines are
+// looked at, so the command's output can't trip it
+fn backend_account_problem(backend: PrivilegeBackend, stderr: &str) -> Option<SudoError> {
+    let prefix = format!("{}: ", backend.program());
+    stderr
+        .lines()
+        .filter(|line| line.starts_with(&prefix))
+        .find_map(account_problem)
+}
+
+fn account_message(problem: &SudoError) -> &'static str {
+    match problem {
+        SudoError::AccountLocked => "Account is locked",
+        _ => "Password has expired and must be changed",
+    }
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(backend = backend.program(), mode = ?config.password_mode)
+)]
+// PrivilegeBackend::verify, with sudo's answer sorted into a Verdict
+fn verify_password(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+    config: &SudoConfig,
+    password: &str,
+    ignore_timestamp: bool,
+) -> Result<Verdict, Box<dyn std::error::Error>> {
+    let timeout = (config.verify_timeout_secs > 0)
+        .then(|| Duration::from_secs(config.verify_timeout_secs));
+    let result = backend.verify(runner, password, config.password_mode, timeout, ignore_timestamp);
+    let output = match result {
+        Ok(Verification::Checked(output)) => output,
+        Ok(Verification::Unsupported) => {
+            tracing::debug!(outcome = "unsupported", "backend can't check passwords");
+            return Ok(Verdict::Unsupported);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+            tracing::warn!(outcome = "timed out", "password check timed out");
+            return Ok(Verdict::TimedOut);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // Informational only, and kept out of the account checks below
+    let (lecture, stderr) = sudoers::split_lecture(&String::from_utf8_lossy(&output.stderr));
+    if let Some(lecture) = lecture {
+        tracing::info!(%lecture, "sudo lectured the user");
+    }
+    if output.status.success() {
+        tracing::debug!(outcome = "accepted", "password verified");
+        return Ok(Verdict::Accepted);
+    }
+
+    Ok(match account_problem(&stderr) {
+        Some(problem) => {
+            tracing::warn!(outcome = ?problem, "account can't authenticate");
+            Verdict::Account(problem)
+        }
+        None => {
+            tracing::info!(outcome = "rejected", "password rejected");
+            Verdict::Rejected
+        }
+    })
+}
+
+// Looks for a NOPASSWD rule covering the request in `sudo -n -l`. Sudo only
+// lists rules without a password when at least one of them has that tag,
+// which is exactly when there's something to find.
+fn runs_without_password(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+) -> bool {
+    // -i runs the command through the target's shell, which a rule has to
+    // allow instead
+    if backend != PrivilegeBackend::Sudo || request.login_shell {
+        return false;
+    }
+    let output = match runner.run(backend.program(), &["-n", "-l"], None) {
+        Ok(output) if output.status.success() => output,
+        _ => return false,
+    };
+
+    let rules = sudoers::parse_permissions(&String::from_utf8_lossy(&output.stdout));
+    let cwd = request.cwd.as_deref();
+    sudoers::allows_nopasswd(
+        &rules,
+        request.run_as.as_deref().unwrap_or("root"),
+        |program| policy::is_allowed(&[program.to_string()], &request.command, cwd),
+        &request.args,
+    )
+}
+
+// A request bringing a password has it checked even over a valid cache, with
+// revalidate_when_password_present
+fn revalidates(config: &SudoConfig, request: &SudoRequest) -> bool {
+    config.revalidate_when_password_present && request.password.is_some()
+}
+
+fn always_prompt(config: &SudoConfig, request: &SudoRequest) -> bool {
+    !config.always_prompt.is_empty()
+        && policy::is_listed(&config.always_prompt, &request.command, request.cwd.as_deref())
+}
+
+// With use_keyring, a request that came without a password gets the stored
+// one. A keyring that can't be read just means asking the user instead.
+fn fill_from_keyring(
+    config: &SudoConfig,
+    passwords: &dyn PasswordStore,
+    request: &mut SudoRequest,
+) {
+    if !config.use_keyring || request.password.is_some() {
+        return;
+    }
+    match passwords.get() {
+        Ok(password) => request.password = password,
+        Err(e) => tracing::warn!("{}", e),
+    }
+}
+
+fn label_prompt(config: &SudoConfig, request: &SudoRequest, response: &mut SudoResponse) {
+    if response.needs_password {
+        response.friendly_label =
+            policy::label_for(&config.command_labels, &request.command, &request.args);
+    }
+}
+
+// Verifies the request's password and seeds the cache on success. Returns the
+// response to hand back when authentication can't go ahead.
+fn authenticate_request(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+    request: &mut SudoRequest,
+    cache: &SudoCache,
+    config: &SudoConfig,
+    key: &CacheKey,
+) -> Option<SudoResponse> {
+    // With nothing to check a password against, asking for one would be
+    // wasted; the run goes ahead with -n
+    if !backend.verifies_passwords() {
+        return None;
+    }
+    // Nor is there any point for a NOPASSWD command, unless the app insists
+    if request.password.is_none()
+        && !always_prompt(config, request)
+        && runs_without_password(runner, backend, request)
+    {
+        tracing::debug!("command is NOPASSWD, skipping the password");
+        return None;
+    }
+
+    let user_id = key.user_id;
+    // Too many wrong passwords, don't even ask sudo until the cooldown is over
+    let lockout = Duration::from_secs(config.lockout_secs);
+    if let Some(remaining) = cache.lockout_remaining(user_id, config.max_failed_attempts, lockout) {
+        tracing::info!(uid = user_id, "password check refused during lockout");
+        return Some(SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some(format!(
+                "locked out, try again in {}s",
+                remaining.as_secs_f64().ceil() as u64
+            )),
+            error_kind: Some(SudoError::LockedOut),
+            cached: false,
+            needs_password: false,
+            ..Default::default()
+        });
+    }
+
+    let revalidate = revalidates(config, request);
+    // No password provided, ask the frontend for one. Once taken, the
+    // password is zeroized when it goes out of scope at the end of this call,
+    // unless askpass mode hands it back for the command's own helper.
+    let password = match request.password.take() {
+        Some(password) => password,
+        None => {
+            return Some(SudoResponse {
+                success: false,
+                output: String::new(),
+                error: Some("Password required".to_string()),
+                error_kind: Some(SudoError::PasswordRequired),
+                cached: false,
+                needs_password: true,
+                ..Default::default()
+            })
+        }
+    };
+
+    // sudo reads a single line, ended by \r or \n, so anything after either
+    // would be left over as if typed at the next prompt. Caught here as well
+    // as in backend::password_line so the response asks for the password again.
+    if password.contains(['\n', '\r']) {
+        return Some(SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some("Password must not contain a line break".to_string()),
+            error_kind: Some(SudoError::InvalidPassword),
+            cached: false,
+            needs_password: true,
+            ..Default::default()
+        });
+    }
+
+    // A command in always_prompt can get here with sudo's timestamp still
+    // valid, as can a revalidated request or a session past
+    // max_session_secs, and then only -k makes sudo actually check the
+    // password. With no timestamp, a normal check creates the one the
+    // command runs with.
+    let ignore_timestamp = (always_prompt(config, request)
+        || revalidate
+        || config.max_session_secs.is_some())
+        && system_timestamp_valid(runner, backend, key);
+    match verify_password(runner, backend, config, &password, ignore_timestamp) {
+        Ok(Verdict::Accepted) => {
+            cache.clear_failures(user_id);
+            cache.clear_reauth(user_id);
+            if config.password_mode == PasswordMode::Askpass {
+                request.password = Some(password);
+            }
+            // A zero timeout means every request re-authenticates
+            if config.timeout_minutes > 0 {
+                cache.authenticate(
+                    key,
+                    config.timeout_minutes,
+                    config.max_session_secs,
+                    request.reason.as_deref(),
+                );
+            }
+            None
+        }
+        Ok(Verdict::Rejected) => {
+            cache.record_failure(user_id);
+            Some(SudoResponse {
+                success: false,
+                output: String::new(),
+                error: Some("Invalid password".to_string()),
+                error_kind: Some(SudoError::InvalidPassword),
+                cached: false,
+                needs_password: true,
+                ..Default::default()
+            })
+        }
+        // Says nothing about the password either way
+        Ok(Verdict::TimedOut) => Some(SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some("password check timed out".to_string()),
+            error_kind: Some(SudoError::Timeout),
+            cached: false,
+            needs_password: false,
+            ..Default::default()
+        }),
+        // Not the password's fault, so it doesn't count towards a lockout
+        Ok(Verdict::Account(problem)) => Some(SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some(account_message(&problem).to_string()),
+            error_kind: Some(problem),
+            cached: false,
+            needs_password: false,
+            ..Default::default()
+        }),
+        // The run itself finds out, with -n
+        Ok(Verdict::Unsupported) => None,
+        Err(e) => Some(SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some(format!("Authentication error: {}", e)),
+            error_kind: Some(SudoError::Backend(e.to_string())),
+            cached: false,
+            needs_password: false,
+            ..Default::default()
+        }),
+    }
+}
+
+// Builds `--preserve-env=A,B` for the variables that should survive sudo's
+// env_reset. The sudoers policy can still refuse to preserve them. Sudo
+// older than 1.8.21 only has the bare -E, which keeps everything.
+fn preserve_env_flag(env: Option<&HashMap<String, String>>, preserve: &[String]) -> Option<String> {
+    let mut names: Vec<&str> = env
+        .into_iter()
+        .flat_map(|env| env.keys())
+        .chain(preserve)
+        .map(String::as_str)
+        .collect();
+
+    if names.is_empty() {
+        return None;
+    }
+
+    // An unknown version is assumed to be a current one
+    if SUDO_VERSION
+        .get()
+        .is_some_and(|version| !version.supports_preserve_env_list())
+    {
+        return Some("-E".to_string());
+    }
+    names.sort_unstable();
+    names.dedup();
+    Some(format!("--preserve-env={}", names.join(",")))
+}
+
+// Rejects a missing or non-directory cwd up front, since spawn only reports a
+// bare "No such file or directory" without saying which path was wrong
+fn check_cwd(cwd: Option<&Path>) -> Option<SudoResponse> {
+    let cwd = cwd?;
+    if cwd.is_dir() {
+        return None;
+    }
+
+    let reason = if cwd.exists() {
+        "is not a directory"
+    } else {
+        "does not exist"
+    };
+
+    let message = format!("Working directory {} {}", cwd.display(), reason);
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(message.clone()),
+        error_kind: Some(SudoError::SpawnFailed(message)),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+// Same for a command that doesn't exist, which sudo would only report after
+// asking for the password. Through a login shell it could be a builtin or on
+// the target user's PATH, so those are left to the shell. Windows resolves
+// extensions itself, so it's left to the spawn there.
+fn check_command(command: &str, cwd: Option<&Path>, login_shell: bool) -> Option<SudoResponse> {
+    if login_shell || cfg!(not(unix)) || policy::canonicalize_command(command, cwd).is_some() {
+        return None;
+    }
+
+    let message = format!("Command not found: {}", command);
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(message.clone()),
+        error_kind: Some(SudoError::SpawnFailed(message)),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+// The backend missing altogether, which would otherwise only come out as a
+// failed password check or spawn
+fn check_backend(backend: PrivilegeBackend) -> Option<SudoResponse> {
+    (!backend.installed()).then(|| no_backend_response(backend))
+}
+
+fn no_backend_response(backend: PrivilegeBackend) -> SudoResponse {
+    SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(format!("{} is not installed", backend.program())),
+        error_kind: Some(SudoError::NoSudoBinary),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    }
+}
+
+// A request's own askpass helper has to be something sudo can run. Only
+// sudo has -A at all.
+fn check_askpass(backend: PrivilegeBackend, askpass: Option<&Path>) -> Option<SudoResponse> {
+    let helper = askpass?;
+    let error = if backend != PrivilegeBackend::Sudo {
+        format!("{} has no askpass support", backend.program())
+    } else if !is_executable(helper) {
+        format!("Askpass helper is not an executable file: {}", helper.display())
+    } else {
+        return None;
+    };
+
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(error.clone()),
+        error_kind: Some(SudoError::SpawnFailed(error)),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+fn check_encoding(label: Option<&str>) -> Option<SudoResponse> {
+    let label = label?;
+    if encoding_rs::Encoding::for_label(label.as_bytes()).is_some() {
+        return None;
+    }
+
+    let error = format!("Unknown output encoding: {}", label);
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(error.clone()),
+        error_kind: Some(SudoError::SpawnFailed(error)),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+// Output as UTF-8, transcoded from `label`'s encoding when there is one.
+// Bytes that don't fit either way become U+FFFD.
+fn decode_output(bytes: &[u8], label: Option<&str>) -> String {
+    match label.and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes())) {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// A request with its own askpass helper leaves the password to it
+fn brings_askpass(backend: PrivilegeBackend, request: &SudoRequest) -> bool {
+    backend == PrivilegeBackend::Sudo && request.askpass.is_some()
+}
+
+// Catches a typo'd target user before sudo does, with a clearer message
+fn check_run_as(run_as: Option<&str>) -> Option<SudoResponse> {
+    let user = run_as?;
+
+    #[cfg(unix)]
+    let error = match nix::unistd::User::from_name(user) {
+        Ok(Some(_)) => return None,
+        Ok(None) => format!("User {} does not exist", user),
+        Err(e) => format!("Failed to look up user {}: {}", user, e),
+    };
+    #[cfg(not(unix))]
+    let error = "Running as another user is not supported on this platform".to_string();
+
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(error),
+        error_kind: Some(SudoError::UnknownUser(user.to_string())),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+// Everything about a single request that can be refused before running it
+fn check_request(
+    config: &SudoConfig,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+) -> Option<SudoResponse> {
+    let cwd = request.cwd.as_deref();
+    check_backend(backend)
+        .or_else(|| check_cwd(cwd))
+        .or_else(|| check_command(&request.command, cwd, request.login_shell))
+        .or_else(|| check_permitted(config, &request.command, &request.args, cwd))
+        .or_else(|| check_run_as(request.run_as.as_deref()))
+        .or_else(|| check_askpass(backend, request.askpass.as_deref()))
+        .or_else(|| check_encoding(request.output_encoding.as_deref()))
+}
+
+// Dry runs spawn nothing and cost nothing
+fn check_rate(
+    config: &SudoConfig,
+    limiter: &RateLimiter,
+    requests: &[SudoRequest],
+) -> Option<SudoResponse> {
+    let cost = requests.iter().filter(|request| !request.dry_run).count() as u32;
+    if cost == 0
+        || limiter.try_acquire(
+            get_current_user_id(),
+            cost,
+            config.rate_limit_per_sec,
+            config.rate_limit_burst,
+        )
+    {
+        return None;
+    }
+
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some("Too many sudo requests, try again shortly".to_string()),
+        error_kind: Some(SudoError::RateLimited),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+// A single string naming exactly what runs, for tying a confirmation token
+// to it
+fn confirmation_subject(request: &SudoRequest) -> String {
+    format!(
+        "{:?}",
+        (
+            policy::display_command(&request.command, request.cwd.as_deref()),
+            &request.args,
+            &request.run_as,
+            request.login_shell,
+        )
+    )
+}
+
+// Requests for commands in require_confirmation_for need a token issued for
+// that very command. All of them are checked before any token is spent, so
+// a batch holding several can be confirmed one response at a time. Dry runs
+// don't need one.
+fn check_confirmation(
+    config: &SudoConfig,
+    confirmations: &Confirmations,
+    requests: &[SudoRequest],
+) -> Option<SudoResponse> {
+    let needed: Vec<(&SudoRequest, String)> = requests
+        .iter()
+        .filter(|request| {
+            !request.dry_run
+                && policy::is_listed(
+                    &config.require_confirmation_for,
+                    &request.command,
+                    request.cwd.as_deref(),
+                )
+        })
+        .map(|request| (request, confirmation_subject(request)))
+        .collect();
+    let claims: Vec<(Option<&str>, &str)> = needed
+        .iter()
+        .map(|(request, subject)| (request.confirmation_token.as_deref(), subject.as_str()))
+        .collect();
+    let unconfirmed = confirmations.redeem(&claims).err()?;
+
+    let (request, subject) = needed.get(unconfirmed)?;
+    let target = request.run_as.as_deref().unwrap_or("root");
+    let (error, error_kind) = match confirmations.issue(subject) {
+        Ok(token) => (
+            format!("Confirm running {} as {}", request.command, target),
+            SudoError::ConfirmationRequired(token),
+        ),
+        Err(e) => (e.clone(), SudoError::Backend(e)),
+    };
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(error),
+        error_kind: Some(error_kind),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+fn check_permitted(
+    config: &SudoConfig,
+    command: &str,
+    args: &[String],
+    cwd: Option<&Path>,
+) -> Option<SudoResponse> {
+    let refused = config
+        .allowlist
+        .as_ref()
+        .is_some_and(|allowlist| !policy::is_allowed(allowlist, command, cwd))
+        || config
+            .arg_validator
+            .is_some_and(|validator| !validator(command, args));
+    if !refused {
+        return None;
+    }
+
+    Some(SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some("command not permitted".to_string()),
+        error_kind: Some(SudoError::NotPermitted),
+        cached: false,
+        needs_password: false,
+        ..Default::default()
+    })
+}
+
+fn sudo_args(
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+) -> Vec<String> {
+    let mut cmd_args = Vec::new();
+    
+    // Sudo must never fall back to prompting on a terminal. Non-interactive
+    // mode for cached auth, and whenever the command gets stdin data that
+    // sudo must not mistake for a password. Otherwise it asks the askpass
+    // helper, or reads stdin, which is empty since the password was already
+    // checked with `-S -v`. A backend that can't take a password any other
+    // way than from the terminal always gets -n.
+    if !backend.handles_authentication() {
+        if use_cached || !backend.verifies_passwords() {
+            cmd_args.push("-n".to_string());
+        } else if request.askpass.is_some() {
+            cmd_args.push("-A".to_string());
+        } else if password_mode == PasswordMode::Stdin && request.stdin.is_some() {
+            cmd_args.push("-n".to_string());
+        } else if password_mode == PasswordMode::Askpass {
+            cmd_args.push("-A".to_string());
+        } else {
+            cmd_args.push("-S".to_string());
+        }
+    }
+
+    if backend.supports_preserve_env() {
+        if let Some(flag) = preserve_env_flag(request.env.as_ref(), &request.preserve_env) {
+            cmd_args.push(flag);
+        }
+    }
+
+    if let Some(user) = &request.run_as {
+        cmd_args.push(backend.run_as_flag().to_string());
+        cmd_args.push(user.clone());
+    }
+
+    if request.login_shell && backend.supports_login_shell() {
+        cmd_args.push("-i".to_string());
+    }
+    
+    cmd_args.push(request.command.clone());
+    cmd_args.extend_from_slice(&request.args);
+    cmd_args
+}
+
+// Runs `env -0` the way the request's command ran, same target user, login
+// shell and preserved variables. Only called once the command has run, so
+// the timestamp is warm and -n is enough.
+fn capture_env(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+) -> Option<HashMap<String, String>> {
+    if backend.handles_authentication() {
+        return None;
+    }
+
+    let probe = SudoRequest {
+        command: "env".to_string(),
+        args: vec!["-0".to_string()],
+        env: request.env.clone(),
+        preserve_env: request.preserve_env.clone(),
+        run_as: request.run_as.clone(),
+        login_shell: request.login_shell,
+        ..Default::default()
+    };
+    let args = sudo_args(backend, &probe, true, PasswordMode::Stdin);
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let locale = inherited_locale();
+    let env: Vec<(&str, &str)> = locale
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .chain(
+            request
+                .env
+                .iter()
+                .flatten()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .collect();
+
+    let output = runner.run_with_env(backend.program(), &args, &env, None).ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    )
+}
+
+// capture_env from async code. Only what the probe copies from the request
+// is handed over to the blocking pool.
+async fn captured_env(
+    runner: &SharedRunner,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+) -> Option<HashMap<String, String>> {
+    let runner = runner.clone();
+    let request = SudoRequest {
+        env: request.env.clone(),
+        preserve_env: request.preserve_env.clone(),
+        run_as: request.run_as.clone(),
+        login_shell: request.login_shell,
+        ..Default::default()
+    };
+    blocking(move || capture_env(runner.as_ref(), backend, &request))
+        .await
+        .ok()
+        .flatten()
+}
+
+// The runner pins the locale to C. This is what the app itself runs with,
+// for probes whose output should look the way the user would see it.
+fn inherited_locale() -> Vec<(&'static str, String)> {
+    ["LANG", "LC_ALL"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok().map(|value| (name, value)))
+        .collect()
+}
+
+// The argv a run spawns, backend first
+fn executed_command(
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+) -> Vec<String> {
+    std::iter::once(backend.program().to_string())
+        .chain(sudo_args(backend, request, use_cached, password_mode))
+        .collect()
+}
+
+// The argv fast_sudo would spawn, with the request's env shown up front as
+// `VAR=value` assignments the way a shell would write it
+fn planned_command(
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+) -> Vec<String> {
+    let mut env: Vec<String> = request
+        .env
+        .iter()
+        .flatten()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    env.sort();
+
+    env.into_iter()
+        .chain(executed_command(backend, request, use_cached, password_mode))
+        .collect()
+}
+
+// Sets SUDO_ASKPASS for a run that goes through `sudo -A`: the request's own
+// helper if it brought one, otherwise one serving the request's password,
+// kept alive until the command exits
+#[cfg(unix)]
+fn attach_askpass(
+    cmd: &mut tokio::process::Command,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+) -> Result<Option<AskpassGuard>, String> {
+    if use_cached || backend.handles_authentication() {
+        return Ok(None);
+    }
+    if let Some(helper) = &request.askpass {
+        cmd.env("SUDO_ASKPASS", helper);
+        return Ok(None);
+    }
+    if password_mode != PasswordMode::Askpass {
+        return Ok(None);
+    }
+    let password = match &request.password {
+        Some(password) => password,
+        None => return Ok(None),
+    };
+
+    let mut askpass = askpass::Askpass::create()
+        .map_err(|e| format!("Failed to set up askpass helper: {}", e))?;
+    let input = backend::password_line(password).map_err(|e| e.to_string())?;
+    askpass.serve(&input);
+    cmd.env("SUDO_ASKPASS", askpass.helper());
+    Ok(Some(askpass))
+}
+
+#[cfg(not(unix))]
+fn attach_askpass(
+    _cmd: &mut tokio::process::Command,
+    _backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+) -> Result<Option<AskpassGuard>, String> {
+    if (password_mode == PasswordMode::Askpass || request.askpass.is_some()) && !use_cached {
+        return Err("askpass mode needs a Unix system".to_string());
+    }
+    Ok(None)
+}
+
+// Keeps the askpass helper around for as long as the command runs
+#[cfg(unix)]
+type AskpassGuard = askpass::Askpass;
+#[cfg(not(unix))]
+type AskpassGuard = ();
+
+#[cfg(unix)]
+type MergedPipe = tokio::net::unix::pipe::Receiver;
+#[cfg(not(unix))]
+type MergedPipe = tokio::io::Empty;
+
+// Pipes the child's stdout and stderr, or with `merge` points both at the same
+// pipe and returns its read end. Reading that gives both streams in the order
+// the command wrote them, but only as far as the command flushes: stdout is
+// block-buffered when it isn't a terminal while stderr usually isn't, so
+// unflushed stdout can land after later stderr. Windows keeps them separate.
+fn pipe_output(
+    cmd: &mut tokio::process::Command,
+    merge: bool,
+) -> Result<Option<MergedPipe>, String> {
+    #[cfg(unix)]
+    if merge {
+        let pipe = tokio::net::unix::pipe::pipe().and_then(|(sender, receiver)| {
+            let writer = sender.into_blocking_fd()?;
+            cmd.stdout(Stdio::from(writer.try_clone()?))
+                .stderr(Stdio::from(writer));
+            Ok(receiver)
+        });
+        return pipe
+            .map(Some)
+            .map_err(|e| format!("Failed to create output pipe: {}", e));
+    }
+    #[cfg(not(unix))]
+    let _ = merge;
+
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    Ok(None)
+}
+
+// Points the streams the request doesn't want at /dev/null, after
+// pipe_output has set them up
+fn discard_uncaptured(cmd: &mut tokio::process::Command, request: &SudoRequest) {
+    if !request.capture_stdout {
+        cmd.stdout(Stdio::null());
+    }
+    if !request.capture_stderr {
+        cmd.stderr(Stdio::null());
+    }
+}
+
+fn uac_response(outcome: uac::Outcome) -> SudoResponse {
+    match outcome {
+        uac::Outcome::Exited(code) => SudoResponse {
+            success: code == 0,
+            output: String::new(),
+            error: None,
+            cached: false,
+            needs_password: false,
+            // NTSTATUS-style codes come out negative, same as std reports them
+            exit_code: Some(code as i32),
+            method: PrivilegeBackend::Uac.program().to_string(),
+            ..Default::default()
+        },
+        uac::Outcome::Declined => SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some("Elevation was declined".to_string()),
+            error_kind: Some(SudoError::Backend("Elevation was declined".to_string())),
+            cached: false,
+            needs_password: false,
+            ..Default::default()
+        },
+        uac::Outcome::TimedOut => SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some("timed out".to_string()),
+            error_kind: Some(SudoError::Timeout),
+            cached: false,
+            needs_password: false,
+            method: PrivilegeBackend::Uac.program().to_string(),
+            ..Default::default()
+        },
+    }
+}
+
+// UAC starts the command outside our process tree: stdin, env and output
+// don't make it across, only the exit code comes back
+async fn run_uac(request: &SudoRequest, config: &SudoConfig) -> Result<SudoResponse, String> {
+    let timeout = request.timeout_secs.or(config.uac_timeout_secs);
+    let outcome = run_uac_blocking(
+        request.command.clone(),
+        request.args.clone(),
+        request.cwd.clone(),
+        timeout.map(Duration::from_secs),
+    )
+    .await?;
+    Ok(uac_response(outcome))
+}
+
+// uac::run waits on the consent dialog and then the command, which mustn't
+// tie up an async worker
+async fn run_uac_blocking(
+    command: String,
+    args: Vec<String>,
+    cwd: Option<PathBuf>,
+    timeout: Option<Duration>,
+) -> Result<uac::Outcome, String> {
+    tokio::task::spawn_blocking(move || uac::run(&command, &args, cwd.as_deref(), timeout))
+        .await
+        .map_err(|e| format!("Failed to execute command: {}", e))?
+}
+
+// Points the command's stdin, stdout and stderr at a new pseudo-terminal and
+// returns its master side twice, to read the output from and to type into.
+// It isn't made the controlling terminal: sudo would then prompt on it and
+// look for a timestamp tied to it, and isatty is all most tools check.
+// Programs that open /dev/tty still won't find one.
+#[cfg(unix)]
+fn attach_pty(
+    cmd: &mut tokio::process::Command,
+) -> Result<(tokio::fs::File, tokio::fs::File), String> {
+    fn open(
+        cmd: &mut tokio::process::Command,
+    ) -> std::io::Result<(tokio::fs::File, tokio::fs::File)> {
+        let size = nix::pty::Winsize {
+            ws_row: 24,
+            ws_col: 80,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = nix::pty::openpty(Some(&size), None)?;
+        cmd.stdin(Stdio::from(pty.slave.try_clone()?))
+            .stdout(Stdio::from(pty.slave.try_clone()?))
+            .stderr(Stdio::from(pty.slave));
+        let input = std::fs::File::from(pty.master.try_clone()?);
+        Ok((std::fs::File::from(pty.master).into(), input.into()))
+    }
+    open(cmd).map_err(|e| format!("Failed to allocate a terminal: {}", e))
+}
+
+#[cfg(not(unix))]
+fn attach_pty(
+    _cmd: &mut tokio::process::Command,
+) -> Result<(tokio::fs::File, tokio::fs::File), String> {
+    Err("use_pty needs a Unix system".to_string())
+}
+
+// Dropping the pipe afterwards closes the command's stdin. A terminal has no
+// end of input, so there the command just reads what was typed.
+async fn write_pipe<W: AsyncWrite + Unpin>(pipe: Option<W>, data: &[u8]) {
+    if let Some(mut pipe) = pipe {
+        // A terminal's file only writes for sure once flushed
+        if pipe.write_all(data).await.is_ok() {
+            let _ = pipe.flush().await;
+        }
+    }
+}
+
+// Reads the pipe to the end, keeping at most `limit` bytes. The rest is still
+// drained so the command doesn't block on a full pipe. Also returns whether
+// anything was dropped.
+async fn read_pipe<R: AsyncRead + Unpin>(
+    mut pipe: Option<R>,
+    limit: Option<usize>,
+) -> (Vec<u8>, bool) {
+    let mut captured = Captured::default();
+    captured.read_from(pipe.as_mut(), limit).await;
+    (captured.bytes, captured.truncated)
+}
+
+// Output read from a pipe so far. Unlike read_pipe's result it outlives a
+// read that's cancelled midway, which is how a killed command's output up to
+// that point is kept.
+#[derive(Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    // More than the limit arrived and was dropped
+    truncated: bool,
+}
+
+impl Captured {
+    // Reads until the pipe closes, keeping at most `limit` bytes in all.
+    // Every chunk is stored as soon as it's read, and another call carries
+    // on where a cancelled one stopped.
+    async fn read_from<R: AsyncRead + Unpin>(
+        &mut self,
+        pipe: Option<&mut R>,
+        limit: Option<usize>,
+    ) {
+        let Some(pipe) = pipe else {
+            return;
+        };
+        let mut chunk = [0u8; 8192];
+        loop {
+            let read = match pipe.read(&mut chunk).await {
+                Ok(0) | Err(_) => return,
+                Ok(read) => read,
+            };
+            let kept = limit.map_or(read, |limit| limit.saturating_sub(self.bytes.len()).min(read));
+            self.bytes.extend_from_slice(&chunk[..kept]);
+            self.truncated |= kept < read;
+        }
+    }
+}
+
+// How long output from a killed command is still read. Anything it started
+// outside its process group may hold the pipes open indefinitely.
+const KILLED_OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+// First wait before spawning again, doubled on each further attempt
+const SPAWN_BACKOFF: Duration = Duration::from_millis(50);
+
+// Spawn failures worth another go: the system was briefly out of processes
+// or memory, or a signal landed mid-fork
+fn is_transient(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted
+    )
+}
+
+// Calls `spawn` until it succeeds, fails for a reason that isn't transient,
+// or has failed `retries` more times, backing off in between
+async fn spawn_with_retries<T>(
+    retries: u32,
+    mut spawn: impl FnMut() -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match spawn() {
+            Err(e) if is_transient(&e) && attempt < retries => {
+                attempt += 1;
+                tracing::debug!(attempt, error = %e, "retrying spawn");
+                tokio::time::sleep(SPAWN_BACKOFF * 2u32.pow(attempt - 1)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// The elevated command is root-owned and can't be signalled from here, but
+// sudo relays SIGTERM to it. Give sudo a moment to pass it on, then SIGKILL
+// whatever is left of the process group.
+async fn terminate(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        use nix::sys::signal::{killpg, Signal};
+        use nix::unistd::Pid;
+
+        let pgid = Pid::from_raw(pid as i32);
+        let _ = killpg(pgid, Signal::SIGTERM);
+        if tokio::time::timeout(Duration::from_secs(2), child.wait())
+            .await
+            .is_ok()
+        {
+            return;
+        }
+        let _ = killpg(pgid, Signal::SIGKILL);
+    }
+
+    // Kills (if still running) and reaps the child so it doesn't linger as a zombie
+    let _ = child.kill().await;
+}
+
+// Why a run was cut short: its timeout passed, or cancel_sudo was called.
+// Never resolves when neither can happen.
+async fn interrupted(timeout_secs: Option<u64>, running: Option<&mut Running>) -> SudoError {
+    let timeout = async {
+        match timeout_secs {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+    let cancelled = async {
+        match running {
+            Some(running) => running.cancelled().await,
+            None => std::future::pending().await,
+        }
+    };
+
+    tokio::select! {
+        _ = timeout => SudoError::Timeout,
+        _ = cancelled => SudoError::Cancelled,
+    }
+}
+
+fn interrupted_response(
+    backend: PrivilegeBackend,
+    reason: SudoError,
+    cached: bool,
+) -> SudoResponse {
+    let message = match reason {
+        SudoError::Cancelled => "cancelled",
+        _ => "timed out",
+    };
+    SudoResponse {
+        success: false,
+        output: String::new(),
+        error: Some(message.to_string()),
+        error_kind: Some(reason),
+        cached,
+        needs_password: false,
+        method: backend.program().to_string(),
+        ..Default::default()
+    }
+}
+
+// A spawned command's pipes, whichever of them it has
+#[derive(Default)]
+struct ChildPipes {
+    stdin: Option<tokio::process::ChildStdin>,
+    stdout: Option<tokio::process::ChildStdout>,
+    stderr: Option<tokio::process::ChildStderr>,
+    merged: Option<MergedPipe>,
+    pty_output: Option<tokio::fs::File>,
+    pty_input: Option<tokio::fs::File>,
+}
+
+// How a command ended, its exit status or why it was cut short, and what it
+// wrote. Merged and terminal output are part of `stdout`.
+struct Finished {
+    status: Result<ExitStatus, SudoError>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
+
+// Feeds the command `input` and reads its output, keeping at most `limit`
+// bytes of each stream, until it exits. Past its timeout, or once cancelled,
+// it's terminated along with its process group instead.
+async fn wait_for_child(
+    mut child: tokio::process::Child,
+    pipes: ChildPipes,
+    input: &[u8],
+    limit: Option<usize>,
+    timeout_secs: Option<u64>,
+    running: Option<&mut Running>,
+) -> Result<Finished, String> {
+    let ChildPipes {
+        stdin: stdin_pipe,
+        stdout: mut stdout_pipe,
+        stderr: mut stderr_pipe,
+        merged: mut merged_pipe,
+        mut pty_output,
+        pty_input,
+    } = pipes;
+    let mut stdout = Captured::default();
+    let mut stderr = Captured::default();
+    let mut merged_output = Captured::default();
+    let mut pty = Captured::default();
+    // Write stdin while draining output, or a command that echoes a lot of
+    // its input could block on a full pipe before reading the rest
+    let run = async {
+        let (status, ..) = tokio::join!(
+            child.wait(),
+            write_pipe(stdin_pipe, input),
+            write_pipe(pty_input, input),
+            stdout.read_from(stdout_pipe.as_mut(), limit),
+            stderr.read_from(stderr_pipe.as_mut(), limit),
+            merged_output.read_from(merged_pipe.as_mut(), limit),
+            // Ends in EIO rather than EOF once the command is gone
+            pty.read_from(pty_output.as_mut(), limit)
+        );
+        status
+    };
+
+    let outcome = tokio::select! {
+        status = run => Ok(status),
+        reason = interrupted(timeout_secs, running) => Err(reason),
+    };
+    if outcome.is_err() {
+        terminate(&mut child).await;
+        // What it wrote before it was killed is still worth having
+        let _ = tokio::time::timeout(KILLED_OUTPUT_GRACE, async {
+            tokio::join!(
+                stdout.read_from(stdout_pipe.as_mut(), limit),
+                stderr.read_from(stderr_pipe.as_mut(), limit),
+                merged_output.read_from(merged_pipe.as_mut(), limit),
+                pty.read_from(pty_output.as_mut(), limit)
+            )
+        })
+        .await;
+    }
+
+    let truncated =
+        stdout.truncated || stderr.truncated || merged_output.truncated || pty.truncated;
+    let mut stdout = stdout.bytes;
+    stdout.extend(merged_output.bytes);
+    stdout.extend(pty.bytes);
+    let stderr = stderr.bytes;
+
+    let status = match outcome {
+        Ok(status) => Ok(status.map_err(|e| format!("Failed to execute command: {}", e))?),
+        Err(reason) => Err(reason),
+    };
+    Ok(Finished {
+        status,
+        stdout,
+        stderr,
+        truncated,
+    })
+}
+
+// Runs the command and records it in the audit log. Runs that sudo refused
+// for lack of authentication never executed anything and aren't logged.
+#[tracing::instrument(
+    skip_all,
+    fields(command = %request.command, cached = use_cached, outcome = tracing::field::Empty)
+)]
+async fn execute_sudo_command(
+    runner: &SharedRunner,
+    backend: PrivilegeBackend,
+    config: &SudoConfig,
+    request: &SudoRequest,
+    use_cached: bool,
+    running: Option<&mut Running>,
+) -> Result<SudoResponse, String> {
+    let started = Instant::now();
+    let mut response = run_sudo_command(
+        runner,
+        backend,
+        request,
+        use_cached,
+        config,
+        running,
+    )
+    .await?;
+    response.duration_ms = started.elapsed().as_millis() as u64;
+    // `method` is only set once something was spawned
+    if !response.method.is_empty() {
+        response.executed_command =
+            executed_command(backend, request, use_cached, config.password_mode);
+    }
+    if request.capture_env && !response.needs_password {
+        response.effective_env = captured_env(runner, backend, request).await;
+    }
+
+    let span = tracing::Span::current();
+    match (&response.error_kind, response.exit_code) {
+        (Some(kind), _) => span.record("outcome", tracing::field::debug(kind)),
+        (None, Some(code)) => span.record("outcome", code),
+        (None, None) => span.record("outcome", "signalled"),
+    };
+    tracing::info!(success = response.success, signal = response.signal, "command finished");
+
+    if !response.needs_password {
+        audit::record(
+            config,
+            get_current_user_id(),
+            &policy::display_command(&request.command, request.cwd.as_deref()),
+            &request.args,
+            &response,
+        );
+    }
+
+    Ok(response)
+}
+
+async fn run_sudo_command(
+    runner: &SharedRunner,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    config: &SudoConfig,
+    running: Option<&mut Running>,
+) -> Result<SudoResponse, String> {
+    let password_mode = config.password_mode;
+    if backend == PrivilegeBackend::Uac {
+        return run_uac(request, config).await;
+    }
+
+    let cmd_args = sudo_args(backend, request, use_cached, password_mode);
+
+    let mut cmd = tokio::process::Command::new(backend.program());
+    cmd.args(&cmd_args)
+        .envs(request.env.iter().flatten())
+        .stdin(if request.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+    let _askpass = attach_askpass(&mut cmd, backend, request, use_cached, password_mode)?;
+    let merged_pipe = pipe_output(&mut cmd, request.merge_streams && !request.use_pty)?;
+    discard_uncaptured(&mut cmd, request);
+    let (pty_output, pty_input) = if request.use_pty {
+        attach_pty(&mut cmd).map(|(output, input)| (Some(output), Some(input)))?
+    } else {
+        (None, None)
+    };
+    let merged = merged_pipe.is_some() || request.use_pty;
+    // No stderr comes back at all when it wasn't captured
+    let stderr_returned = !merged && request.capture_stderr;
+    if let Some(cwd) = &request.cwd {
+        cmd.current_dir(cwd);
+    }
+    runner::spawn_in_new_session(cmd.as_std_mut());
+
+    let mut child = match spawn_with_retries(config.spawn_retries, || cmd.spawn()).await {
+        Ok(child) => child,
+        // check_cwd already vouched for the directory, so it's the backend
+        // that's gone
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(no_backend_response(backend))
+        }
+        Err(e) => {
+            let message = format!("Failed to execute command: {}", e);
+            return Ok(SudoResponse {
+                error: Some(message.clone()),
+                error_kind: Some(SudoError::SpawnFailed(message)),
+                ..Default::default()
+            });
+        }
+    };
+    if let Some(running) = &running {
+        running.track(child.id());
+    }
+    // The command still holds copies of the merged pipe's write end (or the
+    // terminal's slave side), which would keep the read side from ever
+    // seeing EOF
+    drop(cmd);
+
+    let pipes = ChildPipes {
+        stdin: child.stdin.take(),
+        stdout: child.stdout.take(),
+        stderr: child.stderr.take(),
+        merged: merged_pipe,
+        pty_output,
+        pty_input,
+    };
+    let input = request.stdin.as_deref().unwrap_or_default().as_bytes();
+    let limit = request.max_output_bytes;
+    let Finished {
+        status,
+        stdout,
+        stderr,
+        truncated,
+    } = wait_for_child(child, pipes, input, limit, request.timeout_secs, running).await?;
+
+    let encoding = request.output_encoding.as_deref();
+    let status = match status {
+        Ok(status) => status,
+        Err(reason) => {
+            let stderr = decode_output(&stderr, encoding);
+            return Ok(SudoResponse {
+                output_bytes: request.raw_output.then(|| stdout.clone()),
+                output: decode_output(&stdout, encoding),
+                stderr: stderr_returned.then_some(stderr),
+                truncated,
+                ..interrupted_response(backend, reason, use_cached)
+            });
+        }
+    };
+
+    let output_bytes = request.raw_output.then(|| stdout.clone());
+    let stdout = decode_output(&stdout, encoding);
+    let stderr = decode_output(&stderr, encoding);
+    let (exit_code, signal) = exit_details(&status);
+    let success = exit_code == Some(0);
+
+    if success {
+        Ok(SudoResponse {
+            success,
+            output: stdout,
+            error: None,
+            cached: use_cached,
+            needs_password: false,
+            exit_code,
+            signal,
+            method: backend.program().to_string(),
+            truncated,
+            output_bytes,
+            // Warnings from a command that still succeeded
+            stderr: stderr_returned.then_some(stderr),
+            ..Default::default()
+        })
+    } else {
+        // With -n, sudo fails instead of prompting when the timestamp is gone.
+        // That exit status is indistinguishable from the command's own, so
+        // re-probe the timestamp rather than matching (localized) stderr.
+        if let Some(reason) = backend.describe_exit(exit_code) {
+            Ok(SudoResponse {
+                success: false,
+                output: stdout,
+                error: Some(reason.to_string()),
+                error_kind: Some(SudoError::Backend(reason.to_string())),
+                cached: false,
+                needs_password: false,
+                exit_code,
+                signal,
+                method: backend.program().to_string(),
+                truncated,
+                output_bytes,
+                stderr: stderr_returned.then_some(stderr),
+                ..Default::default()
+            })
+        } else if use_cached && !timestamp_still_valid(runner, backend).await? {
+            Ok(SudoResponse {
+                success: false,
+                output: String::new(),
+                error: Some("Authentication required".to_string()),
+                error_kind: Some(SudoError::PasswordRequired),
+                cached: false,
+                needs_password: true,
+                ..Default::default()
+            })
+        } else {
+            // 127 is a shell's "command not found", which is how a missing
+            // command comes back from a login shell (-i), where check_command
+            // can't look ahead
+            let problem = backend_account_problem(backend, &stderr).or_else(|| {
+                let missing = format!("Command not found: {}", request.command);
+                (exit_code == Some(127)).then_some(SudoError::SpawnFailed(missing))
+            });
+            Ok(SudoResponse {
+                success,
+                output: stdout,
+                // Merged stderr is already part of `output`
+                error: (!merged).then(|| stderr.clone()),
+                error_kind: problem,
+                cached: use_cached,
+                needs_password: false,
+                exit_code,
+                signal,
+                method: backend.program().to_string(),
+                truncated,
+                output_bytes,
+                stderr: stderr_returned.then_some(stderr),
+                ..Default::default()
+            })
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn fast_sudo(
+    request: SudoRequest,
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+    passwords: State<'_, SharedPasswordStore>,
+    running: State<'_, RunningCommands>,
+    in_flight: State<'_, InFlightRequests>,
+    limiter: State<'_, RateLimiter>,
+    confirmations: State<'_, Confirmations>,
+) -> Result<SudoResponse, String> {
+    fast_sudo_with(
+        &runner,
+        &passwords,
+        backend.get(),
+        &cache,
+        &config,
+        &running,
+        &in_flight,
+        &limiter,
+        &confirmations,
+        request,
+    )
+    .await
+}
+
+// fast_sudo with every piece of state passed in, so it runs the same way
+// without Tauri
+#[allow(clippy::too_many_arguments)]
+pub async fn fast_sudo_with(
+    runner: &SharedRunner,
+    passwords: &SharedPasswordStore,
+    backend: PrivilegeBackend,
+    cache: &SudoCache,
+    config: &SudoConfig,
+    running: &RunningCommands,
+    in_flight: &InFlightRequests,
+    limiter: &RateLimiter,
+    confirmations: &Confirmations,
+    request: SudoRequest,
+) -> Result<SudoResponse, String> {
+    let requests = std::slice::from_ref(&request);
+    if let Some(response) = check_rate(config, limiter, requests)
+        .or_else(|| check_confirmation(config, confirmations, requests))
+    {
+        return Ok(response);
+    }
+
+    // Dry runs spawn nothing, so there's nothing to save by sharing them
+    let key = (!request.dry_run).then(|| InFlightKey::new(&request));
+    let run = run_privileged_with(runner, passwords, backend, cache, config, running, request);
+    match key {
+        Some(key) => in_flight.coalesce(key, run).await,
+        None => run.await,
+    }
+}
+
+// Runs several commands in order under one password: whichever request
+// carries it is used for all of them, and after the first the warm cache
+// usually means it isn't even checked again. Stops early on a failure when
+// `stop_on_error` is set, and always once authentication itself fails, since
+// retrying the same password would only count towards a lockout. Requests
+// that never ran have no response. The rate limit is charged for the whole
+// batch up front; over it, a single RateLimited response comes back. The same
+// goes for a request still needing confirmation.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn fast_sudo_batch(
+    requests: Vec<SudoRequest>,
+    stop_on_error: bool,
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+    passwords: State<'_, SharedPasswordStore>,
+    running: State<'_, RunningCommands>,
+    limiter: State<'_, RateLimiter>,
+    confirmations: State<'_, Confirmations>,
+) -> Result<Vec<SudoResponse>, String> {
+    fast_sudo_batch_with(
+        &runner,
+        &passwords,
+        backend.get(),
+        &cache,
+        &config,
+        &running,
+        &limiter,
+        &confirmations,
+        requests,
+        stop_on_error,
+    )
+    .await
+}
+
+// fast_sudo_batch with every piece of state passed in
+#[allow(clippy::too_many_arguments)]
+pub async fn fast_sudo_batch_with(
+    runner: &SharedRunner,
+    passwords: &SharedPasswordStore,
+    backend: PrivilegeBackend,
+    cache: &SudoCache,
+    config: &SudoConfig,
+    running: &RunningCommands,
+    limiter: &RateLimiter,
+    confirmations: &Confirmations,
+    mut requests: Vec<SudoRequest>,
+    stop_on_error: bool,
+) -> Result<Vec<SudoResponse>, String> {
+    if let Some(response) = check_rate(config, limiter, &requests)
+        .or_else(|| check_confirmation(config, confirmations, &requests))
+    {
+        return Ok(vec![response]);
+    }
+
+    let password = requests.iter_mut().find_map(|request| request.password.take());
+
+    let mut responses = Vec::with_capacity(requests.len());
+    for mut request in requests {
+        request.password = password.clone();
+        let response =
+            run_privileged_with(runner, passwords, backend, cache, config, running, request)
+                .await?;
+
+        let auth_failed = response.needs_password
+            || matches!(
+                response.error_kind,
+                Some(
+                    SudoError::LockedOut
+                        | SudoError::AccountLocked
+                        | SudoError::PasswordExpired
+                        | SudoError::ReauthPending
+                )
+            );
+        let stop = auth_failed || (stop_on_error && !response.success);
+        responses.push(response);
+        if stop {
+            break;
+        }
+    }
+
+    Ok(responses)
+}
+
+// fast_sudo without Tauri, for a CLI or anything else linking this crate.
+// Uses the system's runner and keyring with the caller's backend, limiter
+// and confirmations, so the same checks apply as in the app. The request is
+// consumed, since its password is taken out and wiped once verified.
+pub async fn run_privileged(
+    cache: &SudoCache,
+    request: SudoRequest,
+    config: &SudoConfig,
+    backend: PrivilegeBackend,
+    limiter: &RateLimiter,
+    confirmations: &Confirmations,
+) -> Result<SudoResponse, String> {
+    let runner: SharedRunner = Arc::new(SystemRunner);
+    let passwords: SharedPasswordStore = Arc::new(KeyringStore::new(
+        &config.keyring_service,
+        keystore::current_user_name(),
+    ));
+    fast_sudo_with(
+        &runner,
+        &passwords,
+        backend,
+        cache,
+        config,
+        &RunningCommands::new(),
+        &InFlightRequests::new(),
+        limiter,
+        confirmations,
+        request,
+    )
+    .await
+}
+
+// Output of run_privileged_lines. As a Stream it yields stdout a line at a
+// time as the command writes it (stderr too under merge_streams); finish
+// then gives the response with the exit status and the rest of stderr.
+// Nothing is buffered beyond a line: the command waits while lines go unread.
+pub struct PrivilegedLines {
+    lines: tokio::sync::mpsc::Receiver<std::io::Result<String>>,
+    finished: tokio::sync::oneshot::Receiver<Result<SudoResponse, String>>,
+}
+
+impl PrivilegedLines {
+    // A run that ended before anything was spawned, e.g. refused for want
+    // of a password
+    fn done(response: SudoResponse) -> Self {
+        let (_, lines) = tokio::sync::mpsc::channel(1);
+        let (sender, finished) = tokio::sync::oneshot::channel();
+        let _ = sender.send(Ok(response));
+        Self { lines, finished }
+    }
+
+    // Waits for the command to exit. Lines that haven't been read are
+    // thrown away.
+    pub async fn finish(self) -> Result<SudoResponse, String> {
+        drop(self.lines);
+        self.finished
+            .await
+            .unwrap_or_else(|_| Err("Command ended without a status".to_string()))
+    }
+}
+
+impl futures_core::Stream for PrivilegedLines {
+    type Item = std::io::Result<String>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.lines.poll_recv(cx)
+    }
+}
+
+// run_privileged for output that's better handled as it arrives than as
+// one string at the end, like logs. Authenticates the way fast_sudo_stream
+// does, then spawns the command the same way; a refused run comes back as
+// a PrivilegedLines with no lines whose finish returns the refusal.
+pub async fn run_privileged_lines(
+    cache: &SudoCache,
+    mut request: SudoRequest,
+    config: &SudoConfig,
+    backend: PrivilegeBackend,
+    limiter: &RateLimiter,
+    confirmations: &Confirmations,
+) -> Result<PrivilegedLines, String> {
+    let requests = std::slice::from_ref(&request);
+    if let Some(response) = check_rate(config, limiter, requests)
+        .or_else(|| check_confirmation(config, confirmations, requests))
+        .or_else(|| check_request(config, backend, &request))
+    {
+        return Ok(PrivilegedLines::done(response));
+    }
+
+    // UAC output can't be captured at all
+    if backend == PrivilegeBackend::Uac {
+        return Ok(PrivilegedLines::done(run_uac(&request, config).await?));
+    }
+
+    let runner: SharedRunner = Arc::new(SystemRunner);
+    let passwords: SharedPasswordStore = Arc::new(KeyringStore::new(
+        &config.keyring_service,
+        keystore::current_user_name(),
+    ));
+    let key = CacheKey::new(backend, get_current_user_id(), request.run_as.as_deref());
+    let authorized = authorize_streamed(
+        &runner,
+        &passwords,
+        backend,
+        cache,
+        config,
+        &mut request,
+        &key,
+    )
+    .await;
+    let (use_cached, auth_duration_ms) = match authorized {
+        Ok(authorized) => authorized,
+        Err(response) => return Ok(PrivilegedLines::done(*response)),
+    };
+
+    let started = Instant::now();
+    let StreamedChild {
+        mut child,
+        merged,
+        _askpass,
+    } = spawn_streamed(backend, &request, use_cached, config.password_mode)?;
+    let stdout: Option<Box<dyn AsyncRead + Unpin + Send>> = match merged {
+        Some(merged) => Some(Box::new(merged)),
+        None => child.stdout.take().map(|stdout| Box::new(stdout) as _),
+    };
+    let stderr = child.stderr.take();
+
+    let (lines, receiver) = tokio::sync::mpsc::channel(1);
+    let (sender, finished) = tokio::sync::oneshot::channel();
+    let cache = cache.clone();
+    let config = config.clone();
+    tokio::spawn(async move {
+        let _askpass = _askpass;
+        let run = async {
+            let (status, _, (stderr, _)) = tokio::join!(
+                child.wait(),
+                send_lines(stdout, lines),
+                read_pipe(stderr, request.max_output_bytes)
+            );
+            status.map(|status| (status, stderr))
+        };
+        let outcome = tokio::select! {
+            result = run => Ok(result),
+            reason = interrupted(request.timeout_secs, None) => Err(reason),
+        };
+
+        let response = match outcome {
+            Ok(Ok((status, stderr))) => {
+                let (exit_code, signal) = exit_details(&status);
+                Ok(SudoResponse {
+                    success: exit_code == Some(0),
+                    output: String::new(),
+                    error: None,
+                    cached: use_cached,
+                    needs_password: false,
+                    exit_code,
+                    signal,
+                    method: backend.program().to_string(),
+                    stderr: (!request.merge_streams)
+                        .then(|| String::from_utf8_lossy(&stderr).into_owned()),
+                    ..Default::default()
+                })
+            }
+            Ok(Err(e)) => Err(format!("Failed to wait for command: {}", e)),
+            Err(reason) => {
+                terminate(&mut child).await;
+                Ok(interrupted_response(backend, reason, use_cached))
+            }
+        };
+        let response = response.map(|mut response| {
+            response.duration_ms = started.elapsed().as_millis() as u64;
+            response.auth_duration_ms = auth_duration_ms;
+            response.executed_command =
+                executed_command(backend, &request, use_cached, config.password_mode);
+            if response.success && use_cached && config.sliding_expiry {
+                cache.refresh(&key, config.timeout_minutes);
+            }
+            audit::record(
+                &config,
+                key.user_id,
+                &policy::display_command(&request.command, request.cwd.as_deref()),
+                &request.args,
+                &response,
+            );
+            response
+        });
+        let _ = sender.send(response);
+    });
+
+    Ok(PrivilegedLines {
+        lines: receiver,
+        finished,
+    })
+}
+
+// Hands each line of the pipe to run_privileged_lines' stream. Once nobody
+// is reading, the rest is drained unread so the command can still finish.
+async fn send_lines<R: AsyncRead + Unpin>(
+    pipe: Option<R>,
+    lines: tokio::sync::mpsc::Sender<std::io::Result<String>>,
+) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let mut reader = BufReader::new(pipe);
+    let mut buf = Vec::new();
+    loop {
+        let line = match read_line_capped(&mut reader, &mut buf, MAX_STREAM_LINE_BYTES).await {
+            Ok(true) => Ok(String::from_utf8_lossy(&buf).into_owned()),
+            Ok(false) => return,
+            Err(e) => Err(e),
+        };
+        let failed = line.is_err();
+        if lines.send(line).await.is_err() {
+            let _ = tokio::io::copy(&mut reader, &mut tokio::io::sink()).await;
+            return;
+        }
+        if failed {
+            return;
+        }
+    }
+}
+
+// Same, with every dependency supplied, e.g. stand-ins in tests. Runs given a
+// request id can be stopped through `running`.
+#[tracing::instrument(
+    skip_all,
+    fields(uid = get_current_user_id(), command = %request.command, run_as = ?request.run_as)
+)]
+pub async fn run_privileged_with(
+    runner: &SharedRunner,
+    passwords: &SharedPasswordStore,
+    backend: PrivilegeBackend,
+    cache: &SudoCache,
+    config: &SudoConfig,
+    running: &RunningCommands,
+    mut request: SudoRequest,
+) -> Result<SudoResponse, String> {
+    if let Some(response) = check_request(config, backend, &request) {
+        return Ok(response);
+    }
+
+    // Only peeks at the cache, nothing is pruned, verified or spawned
+    if request.dry_run {
+        let use_cached = !backend.handles_authentication()
+            && !always_prompt(config, &request)
+            && !revalidates(config, &request)
+            && cache.is_authenticated(&CacheKey::new(
+                backend,
+                get_current_user_id(),
+                request.run_as.as_deref(),
+            ));
+
+        return Ok(SudoResponse {
+            success: true,
+            output: String::new(),
+            error: None,
+            cached: use_cached,
+            needs_password: false,
+            method: backend.program().to_string(),
+            planned_command: Some(planned_command(
+                backend,
+                &request,
+                use_cached,
+                config.password_mode,
+            )),
+            ..Default::default()
+        });
+    }
+
+    let mut running = Some(running.register(request.request_id.as_deref())?);
+
+    // polkit runs its own dialog, there's no password or cache to manage
+    if backend.handles_authentication() {
+        return execute_sudo_command(runner, backend, config, &request, false, running.as_mut())
+            .await;
+    }
+
+    let key = CacheKey::new(backend, get_current_user_id(), request.run_as.as_deref());
+    let timeout_minutes = config.timeout_minutes;
+
+    let mut needs_auth = true;
+    let mut use_cached = false;
+
+    // Check if already authenticated
+    let authenticated = if always_prompt(config, &request) || revalidates(config, &request) {
+        false
+    } else {
+        let (runner, cache, config, key) =
+            (runner.clone(), cache.clone(), config.clone(), key.clone());
+        blocking(move || {
+            if cache.is_authenticated(&key) {
+                confirm_cached(runner.as_ref(), &cache, &key, backend)
+            } else {
+                seed_from_system(runner.as_ref(), &cache, &key, &config, backend)
+            }
+        })
+        .await?
+    };
+    if authenticated {
+        use_cached = true;
+        needs_auth = false;
+    }
+    tracing::debug!(cached = use_cached, "checked cached authentication");
+
+    // If we have cached auth, try to use it first
+    if use_cached {
+        let response =
+            execute_sudo_command(runner, backend, config, &request, true, running.as_mut()).await;
+        match response {
+            Ok(response) => {
+                if response.success {
+                    if config.sliding_expiry {
+                        cache.refresh(&key, timeout_minutes);
+                    }
+                    return Ok(response);
+                } else if response.needs_password {
+                    // The timestamp went away after it was checked, say by
+                    // a `sudo -k` elsewhere, so nothing ran. Falls through
+                    // to authenticating and running once more, uncached.
+                    // Without a password that means a prompt, and only one
+                    // request in a burst gets to ask for it.
+                    tracing::info!("cached authentication was invalidated externally");
+                    cache.remove(&key);
+                    fill_from_keyring(config, passwords.as_ref(), &mut request);
+                    if request.password.is_none() && !brings_askpass(backend, &request) {
+                        if let Err(remaining) = cache.begin_reauth(key.user_id) {
+                            return Ok(SudoResponse {
+                                success: false,
+                                output: String::new(),
+                                error: Some(format!(
+                                    "re-authentication already requested, retry in {}s",
+                                    remaining.as_secs_f64().ceil() as u64
+                                )),
+                                error_kind: Some(SudoError::ReauthPending),
+                                cached: false,
+                                needs_password: false,
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    needs_auth = true;
+                } else {
+                    return Ok(response);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut auth_duration_ms = None;
+    if needs_auth && !brings_askpass(backend, &request) {
+        let started = Instant::now();
+        let refused = {
+            let (runner, passwords, cache, config, key) = (
+                runner.clone(),
+                passwords.clone(),
+                cache.clone(),
+                config.clone(),
+                key.clone(),
+            );
+            let mut lent = std::mem::take(&mut request);
+            let (refused, lent) = blocking(move || {
+                fill_from_keyring(&config, passwords.as_ref(), &mut lent);
+                let refused = authenticate_request(
+                    runner.as_ref(),
+                    backend,
+                    &mut lent,
+                    &cache,
+                    &config,
+                    &key,
+                );
+                (refused, lent)
+            })
+            .await?;
+            request = lent;
+            refused
+        };
+        auth_duration_ms = Some(started.elapsed().as_millis() as u64);
+        if let Some(mut response) = refused {
+            response.auth_duration_ms = auth_duration_ms;
+            label_prompt(config, &request, &mut response);
+            return Ok(response);
+        }
+    }
+
+    // Execute the command
+    let mut response =
+        execute_sudo_command(runner, backend, config, &request, false, running.as_mut()).await?;
+    response.auth_duration_ms = auth_duration_ms;
+    Ok(response)
+}
+
+// Longest line sent as one event. The rest of a longer line is dropped, so a
+// command that never prints a newline can't grow the buffer without bound.
+const MAX_STREAM_LINE_BYTES: usize = 64 * 1024;
+
+// Reads a line into `line` without its terminator, keeping at most `max` bytes
+// of it. Returns false once the input is exhausted. A line is only handed
+// back once its newline (or the end of input) arrives, so a character split
+// across reads comes back whole; one split by `max` is dropped. Reads cut
+// short by a signal are retried.
+async fn read_line_capped<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    max: usize,
+) -> std::io::Result<bool> {
+    line.clear();
+    let mut read_any = false;
+    let mut capped = false;
+
+    loop {
+        let available = match reader.fill_buf().await {
+            Ok(available) => available,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            if capped {
+                line.truncate(utf8_boundary(line));
+            }
+            return Ok(read_any);
+        }
+        read_any = true;
+
+        let (chunk, complete) = match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (&available[..end], true),
+            None => (available, false),
+        };
+        let room = max.saturating_sub(line.len());
+        capped |= chunk.len() > room;
+        line.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        let consumed = chunk.len() + usize::from(complete);
+        reader.consume(consumed);
+
+        if complete {
+            if capped {
+                line.truncate(utf8_boundary(line));
+            } else if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            return Ok(true);
+        }
+    }
+}
+
+// How much of `bytes` is left once a UTF-8 sequence cut off at the end is
+// dropped. Anything else invalid is kept, for the lossy decode to replace.
+fn utf8_boundary(bytes: &[u8]) -> usize {
+    let tail = bytes.len().saturating_sub(4);
+    let Some(start) = (tail..bytes.len()).rev().find(|&i| bytes[i] & 0xc0 != 0x80) else {
+        return bytes.len();
+    };
+    let needed = match bytes[start] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    if bytes.len() - start < needed {
+        start
+    } else {
+        bytes.len()
+    }
+}
+
+// Forwards each line of a child pipe to the frontend as soon as it's read.
+// Lines the progress parser recognizes go out as `sudo://progress` instead.
+fn forward_lines<R>(
+    app_handle: AppHandle,
+    event: &'static str,
+    request_id: String,
+    reader: R,
+    progress: Option<ProgressKind>,
+) -> tokio::task::JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        // The next line isn't read until this one has gone out, so if we fall
+        // behind, the command blocks on a full pipe rather than output piling
+        // up here. emit itself doesn't wait for the frontend to catch up.
+        while let Ok(true) = read_line_capped(&mut reader, &mut buf, MAX_STREAM_LINE_BYTES).await {
+            let line = String::from_utf8_lossy(&buf).into_owned();
+            if let Some(percent) = progress.and_then(|kind| progress::parse(kind, &line)) {
+                let _ = app_handle.emit(
+                    "sudo://progress",
+                    SudoStreamProgress {
+                        request_id: request_id.clone(),
+                        percent,
+                    },
+                );
+                continue;
+            }
+
+            let _ = app_handle.emit(
+                event,
+                SudoStreamLine {
+                    request_id: request_id.clone(),
+                    line,
+                },
+            );
+        }
+    })
+}
+
+// A command spawned for reading line by line. The askpass helper, if there
+// is one, has to outlive it.
+struct StreamedChild {
+    child: tokio::process::Child,
+    merged: Option<MergedPipe>,
+    _askpass: Option<AskpassGuard>,
+}
+
+// Spawns the command for fast_sudo_stream and run_privileged_lines, with no
+// stdin and its output piped (or merged, see pipe_output)
+fn spawn_streamed(
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+) -> Result<StreamedChild, String> {
+    let mut cmd = tokio::process::Command::new(backend.program());
+    cmd.args(sudo_args(backend, request, use_cached, password_mode))
+        .envs(request.env.iter().flatten());
+    let askpass = attach_askpass(&mut cmd, backend, request, use_cached, password_mode)?;
+    if let Some(cwd) = &request.cwd {
+        cmd.current_dir(cwd);
+    }
+
+    cmd.stdin(Stdio::null());
+    let merged_pipe = pipe_output(&mut cmd, request.merge_streams)?;
+    discard_uncaptured(&mut cmd, request);
+    runner::spawn_in_new_session(cmd.as_std_mut());
+
+    let child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to execute command: {}", e))?;
+    Ok(StreamedChild {
+        child,
+        merged: merged_pipe,
+        _askpass: askpass,
+    })
+}
+
+// Spawns the command for fast_sudo_stream and forwards its output line by line
+async fn stream_command(
+    app_handle: &AppHandle,
+    request_id: &str,
+    backend: PrivilegeBackend,
+    request: &SudoRequest,
+    use_cached: bool,
+    password_mode: PasswordMode,
+    running: &mut Running,
+) -> Result<SudoResponse, String> {
+    let StreamedChild {
+        mut child,
+        merged: merged_pipe,
+        _askpass,
+    } = spawn_streamed(backend, request, use_cached, password_mode)?;
+    running.track(child.id());
+
+    // Drain both pipes concurrently so a chatty stderr can't block stdout.
+    // Merged output all goes out as `sudo://stdout`.
+    // Package managers report progress on stdout.
+    let stdout_task = match merged_pipe {
+        Some(merged) => Some(forward_lines(
+            app_handle.clone(),
+            "sudo://stdout",
+            request_id.to_string(),
+            merged,
+            request.progress_parser,
+        )),
+        None => child.stdout.take().map(|stdout| {
+            forward_lines(
+                app_handle.clone(),
+                "sudo://stdout",
+                request_id.to_string(),
+                stdout,
+                request.progress_parser,
+            )
+        }),
+    };
+    let stderr_task = child.stderr.take().map(|stderr| {
+        forward_lines(
+            app_handle.clone(),
+            "sudo://stderr",
+            request_id.to_string(),
+            stderr,
+            None,
+        )
+    });
+
+    let status = tokio::select! {
+        status = child.wait() => Some(status),
+        _ = running.cancelled() => None,
+    };
+    if status.is_none() {
+        terminate(&mut child).await;
+    }
+
+    // Make sure every line has been emitted before signalling completion
+    for task in [stdout_task, stderr_task].into_iter().flatten() {
+        let _ = task.await;
+    }
+
+    let status = match status {
+        Some(status) => status.map_err(|e| format!("Failed to wait for command: {}", e))?,
+        None => return Ok(interrupted_response(backend, SudoError::Cancelled, use_cached)),
+    };
+
+    let (exit_code, signal) = exit_details(&status);
+    Ok(SudoResponse {
+        success: exit_code == Some(0),
+        output: String::new(),
+        error: None,
+        cached: use_cached,
+        needs_password: false,
+        exit_code,
+        signal,
+        method: backend.program().to_string(),
+        ..Default::default()
+    })
+}
+
+// Runs the command under `sudo -S` with stdin kept open for answers, for
+// PAM stacks that ask more than a password (OTP codes, a new password).
+// Output streams like stream_command's, never merged, since questions are
+// spotted on stderr. A command the timestamp already covers simply never
+// prompts and comes back `cached`.
+async fn run_interactive(
+    app_handle: &AppHandle,
+    request_id: &str,
+    backend: PrivilegeBackend,
+    request: &mut SudoRequest,
+    prompts: &PendingPrompts,
+    max_prompts: u32,
+    running: &mut Running,
+) -> Result<SudoResponse, String> {
+    if request.stdin.is_some() {
+        return Err("Interactive runs can't take stdin, it carries the answers".to_string());
+    }
+    if request.askpass.is_some() {
+        return Err("Interactive runs answer sudo's prompts themselves, not askpass".to_string());
+    }
+
+    let mut cmd = tokio::process::Command::new(backend.program());
+    cmd.args(sudo_args(backend, request, false, PasswordMode::Stdin))
+        .envs(request.env.iter().flatten())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cwd) = &request.cwd {
+        cmd.current_dir(cwd);
+    }
+    runner::spawn_in_new_session(cmd.as_std_mut());
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to execute command: {}", e))?;
+    running.track(child.id());
+
+    let stdout_task = child.stdout.take().map(|stdout| {
+        forward_lines(
+            app_handle.clone(),
+            "sudo://stdout",
+            request_id.to_string(),
+            stdout,
+            request.progress_parser,
+        )
+    });
+    let (stdin, stderr) = match (child.stdin.take(), child.stderr.take()) {
+        (Some(stdin), Some(stderr)) => (stdin, stderr),
+        _ => return Err("Failed to execute command: pipes unavailable".to_string()),
+    };
+    let password = request.password.take();
+
+    let outcome = tokio::select! {
+        result = async {
+            let answered = converse(
+                app_handle,
+                request_id,
+                stdin,
+                stderr,
+                prompts,
+                password,
+                max_prompts,
+            )
+            .await;
+            (answered, child.wait().await)
+        } => Ok(result),
+        reason = interrupted(request.timeout_secs, Some(running)) => Err(reason),
+    };
+    let (answered, status) = match outcome {
+        Ok((Ok(answered), status)) => (answered, status),
+        Ok((Err(reason), _)) | Err(reason) => {
+            terminate(&mut child).await;
+            if let Some(task) = stdout_task {
+                let _ = task.await;
+            }
+            let mut response = interrupted_response(backend, reason.clone(), false);
+            if reason == SudoError::TooManyPrompts {
+                response.error = Some(format!("gave up after {} prompts", max_prompts));
+            }
+            return Ok(response);
+        }
+    };
+    if let Some(task) = stdout_task {
+        let _ = task.await;
+    }
+
+    let status = status.map_err(|e| format!("Failed to wait for command: {}", e))?;
+    let (exit_code, signal) = exit_details(&status);
+    Ok(SudoResponse {
+        success: exit_code == Some(0),
+        output: String::new(),
+        error: None,
+        cached: answered == 0,
+        needs_password: false,
+        exit_code,
+        signal,
+        method: backend.program().to_string(),
+        ..Default::default()
+    })
+}
+
+// Reads stderr for run_interactive. Finished lines go out as `sudo://stderr`;
+// text left hanging that looks like a question (see prompt::is_prompt) is
+// answered on stdin, with the request's password the first time if it came
+// with one, otherwise by asking the frontend. Anything the command itself
+// asks that way after sudo is done gets relayed too. Returns how many
+// questions were answered, once stderr closes.
+async fn converse(
+    app_handle: &AppHandle,
+    request_id: &str,
+    mut stdin: tokio::process::ChildStdin,
+    mut stderr: tokio::process::ChildStderr,
+    prompts: &PendingPrompts,
+    mut password: Option<Zeroizing<String>>,
+    max_prompts: u32,
+) -> Result<u32, SudoError> {
+    let emit_line = |line: &[u8]| {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let _ = app_handle.emit(
+            "sudo://stderr",
+            SudoStreamLine {
+                request_id: request_id.to_string(),
+                line: String::from_utf8_lossy(line).into_owned(),
+            },
+        );
+    };
+
+    let mut pending = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let mut answered = 0;
+    loop {
+        let read = match stderr.read(&mut chunk).await {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        pending.extend_from_slice(&chunk[..read]);
+        while let Some(end) = pending.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            emit_line(&line[..end]);
+        }
+        // Sent on in pieces, each ending on a whole character
+        if pending.len() > MAX_STREAM_LINE_BYTES {
+            let end = utf8_boundary(&pending);
+            let piece: Vec<u8> = pending.drain(..end).collect();
+            emit_line(&piece);
+            continue;
+        }
+
+        let question = String::from_utf8_lossy(&pending).trim_end().to_string();
+        if !prompt::is_prompt(&question) {
+            continue;
+        }
+        pending.clear();
+        if answered == max_prompts {
+            return Err(SudoError::TooManyPrompts);
+        }
+        answered += 1;
+
+        let answer = match password.take() {
+            Some(password) => password,
+            None => {
+                let reply = prompts.wait(request_id);
+                let _ = app_handle.emit(
+                    "sudo://prompt",
+                    SudoStreamPrompt {
+                        request_id: request_id.to_string(),
+                        prompt: question,
+                    },
+                );
+                reply.await.map_err(|_| SudoError::Cancelled)?
+            }
+        };
+        let mut line = Zeroizing::new(Vec::with_capacity(answer.len() + 1));
+        line.extend_from_slice(answer.as_bytes());
+        line.push(b'\n');
+        if stdin.write_all(&line).await.is_err() {
+            break;
+        }
+    }
+
+    if !pending.is_empty() {
+        emit_line(&pending);
+    }
+    Ok(answered)
+}
+
+// Output goes out as it arrives, so a streamed run can't be retried after a
+// failed cached attempt like fast_sudo does. The system timestamp is
+// confirmed up front instead, and the password checked if that fails.
+// Returns whether the cache is used and how long any password check took,
+// or the response refusing the run. polkit runs its own dialog and skips
+// all of this.
+async fn authorize_streamed(
+    runner: &SharedRunner,
+    passwords: &SharedPasswordStore,
+    backend: PrivilegeBackend,
+    cache: &SudoCache,
+    config: &SudoConfig,
+    request: &mut SudoRequest,
+    key: &CacheKey,
+) -> Result<(bool, Option<u64>), Box<SudoResponse>> {
+    let (runner, passwords, cache, config, key) = (
+        runner.clone(),
+        passwords.clone(),
+        cache.clone(),
+        config.clone(),
+        key.clone(),
+    );
+    let mut lent = std::mem::take(request);
+    let authorized = blocking(move || {
+        let authorized = authorize_blocking(
+            runner.as_ref(),
+            passwords.as_ref(),
+            backend,
+            &cache,
+            &config,
+            &mut lent,
+            &key,
+        );
+        (authorized, lent)
+    })
+    .await;
+    match authorized {
+        Ok((authorized, lent)) => {
+            *request = lent;
+            authorized
+        }
+        Err(e) => Err(Box::new(SudoResponse {
+            error: Some(e.clone()),
+            error_kind: Some(SudoError::Backend(e)),
+            ..Default::default()
+        })),
+    }
+}
+
+// authorize_streamed's part that waits on sudo
+fn authorize_blocking(
+    runner: &dyn CommandRunner,
+    passwords: &dyn PasswordStore,
+    backend: PrivilegeBackend,
+    cache: &SudoCache,
+    config: &SudoConfig,
+    request: &mut SudoRequest,
+    key: &CacheKey,
+) -> Result<(bool, Option<u64>), Box<SudoResponse>> {
+    if backend.handles_authentication() {
+        return Ok((false, None));
+    }
+
+    let use_cached = if always_prompt(config, request) || revalidates(config, request) {
+        false
+    } else if cache.is_authenticated(key) {
+        let valid = sudo_timestamp_valid(runner, backend);
+        if !valid {
+            cache.remove(key);
+        }
+        valid
+    } else {
+        seed_from_system(runner, cache, key, config, backend)
+    };
+    if use_cached || brings_askpass(backend, request) {
+        return Ok((use_cached, None));
+    }
+
+    fill_from_keyring(config, passwords, request);
+    let started = Instant::now();
+    let refused = authenticate_request(runner, backend, request, cache, config, key);
+    let auth_duration_ms = Some(started.elapsed().as_millis() as u64);
+    match refused {
+        Some(mut response) => {
+            response.auth_duration_ms = auth_duration_ms;
+            label_prompt(config, request, &mut response);
+            Err(Box::new(response))
+        }
+        None => Ok((false, auth_duration_ms)),
+    }
+}
+
+// Like fast_sudo, but emits `sudo://stdout` / `sudo://stderr` events per line
+// (or `sudo://progress`, see progress_parser) while the command runs and a
+// final `sudo://exit` event. The returned response carries the exit status but
+// no output. With `interactive`, `sudo://prompt` events come first, each to be
+// answered with respond_sudo_prompt.
+// Tauri injects each piece of managed state as its own argument
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn fast_sudo_stream(
+    app_handle: AppHandle,
+    request_id: String,
+    mut request: SudoRequest,
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+    passwords: State<'_, SharedPasswordStore>,
+    running: State<'_, RunningCommands>,
+    limiter: State<'_, RateLimiter>,
+    prompts: State<'_, PendingPrompts>,
+    confirmations: State<'_, Confirmations>,
+) -> Result<SudoResponse, String> {
+    let backend = backend.get();
+    if let Some(response) = check_request(&config, backend, &request)
+        .or_else(|| check_rate(&config, &limiter, std::slice::from_ref(&request)))
+        .or_else(|| check_confirmation(&config, &confirmations, std::slice::from_ref(&request)))
+    {
+        return Ok(response);
+    }
+
+    let mut running = running.register(Some(&request_id))?;
+
+    let key = CacheKey::new(backend, get_current_user_id(), request.run_as.as_deref());
+    let timeout_minutes = config.timeout_minutes;
+    // Interactive runs leave authentication to sudo, whatever it asks
+    let interactive = request.interactive && backend.supports_stdin_password();
+    let authorized = if interactive {
+        Ok((false, None))
+    } else {
+        authorize_streamed(
+            &runner,
+            &passwords,
+            backend,
+            &cache,
+            &config,
+            &mut request,
+            &key,
+        )
+        .await
+    };
+    let (use_cached, auth_duration_ms) = match authorized {
+        Ok(authorized) => authorized,
+        Err(response) => return Ok(*response),
+    };
+
+    // UAC output can't be captured, only the exit event goes out
+    let started = Instant::now();
+    let mut response = if backend == PrivilegeBackend::Uac {
+        run_uac(&request, &config).await?
+    } else if interactive {
+        let response = run_interactive(
+            &app_handle,
+            &request_id,
+            backend,
+            &mut request,
+            &prompts,
+            config.max_prompts,
+            &mut running,
+        )
+        .await;
+        prompts.forget(&request_id);
+        let response = response?;
+        // Sudo's timestamp is fresh after a password it accepted
+        if response.success && !response.cached && timeout_minutes > 0 {
+            let reason = request.reason.as_deref();
+            cache.authenticate(&key, timeout_minutes, config.max_session_secs, reason);
+        }
+        response
+    } else {
+        stream_command(
+            &app_handle,
+            &request_id,
+            backend,
+            &request,
+            use_cached,
+            config.password_mode,
+            &mut running,
+        )
+        .await?
+    };
+    response.duration_ms = started.elapsed().as_millis() as u64;
+    response.auth_duration_ms = auth_duration_ms;
+    if !response.method.is_empty() {
+        // Interactive runs always hand sudo the password on stdin
+        let password_mode = match interactive {
+            true => PasswordMode::Stdin,
+            false => config.password_mode,
+        };
+        response.executed_command =
+            executed_command(backend, &request, use_cached, password_mode);
+    }
+    if request.capture_env && !response.needs_password {
+        response.effective_env = captured_env(&runner, backend, &request).await;
+    }
+
+    if response.success && use_cached && config.sliding_expiry {
+        cache.refresh(&key, timeout_minutes);
+    }
+
+    audit::record(
+        &config,
+        get_current_user_id(),
+        &policy::display_command(&request.command, request.cwd.as_deref()),
+        &request.args,
+        &response,
+    );
+
+    let _ = app_handle.emit(
+        "sudo://exit",
+        SudoStreamExit {
+            request_id,
+            success: response.success,
+            exit_code: response.exit_code,
+            signal: response.signal,
+            cached: response.cached,
+        },
+    );
+
+    Ok(response)
+}
+
+// Answers the question an interactive fast_sudo_stream run is waiting on.
+// Returns false when that run isn't waiting for anything.
+#[tauri::command]
+pub async fn respond_sudo_prompt(
+    request_id: String,
+    response: Zeroizing<String>,
+    prompts: State<'_, PendingPrompts>,
+) -> Result<bool, String> {
+    // sudo reads a line, up to \r or \n, the rest would answer the next question
+    if response.contains(['\n', '\r']) {
+        return Err("Response must not contain a line break".to_string());
+    }
+    Ok(prompts.respond(&request_id, response))
+}
+
+static PROMPT_TEXT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+// The password prompt sudo itself would show, from sudoers' passprompt or
+// PAM and in the user's language, for the app's own dialog to reuse. None
+// when sudo wouldn't prompt at all, e.g. NOPASSWD or a valid timestamp, or
+// for other backends. See probe_prompt for what finding it costs.
+#[tauri::command]
+pub async fn sudo_prompt_text(
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<Option<String>, String> {
+    let backend = backend.get();
+    if backend != PrivilegeBackend::Sudo {
+        return Ok(None);
+    }
+    if let Some(prompt) = PROMPT_TEXT.get() {
+        return Ok(Some(prompt.clone()));
+    }
+
+    let runner = runner.inner().clone();
+    let prompt = blocking(move || probe_prompt(runner.as_ref(), backend)).await??;
+    // Only a prompt that was found is kept, a failed probe can be retried
+    Ok(prompt.map(|prompt| PROMPT_TEXT.get_or_init(|| prompt).clone()))
+}
+
+// Lets `sudo -S -p PROMPT_PROBE -v` prompt on an empty stdin. There's no -k,
+// so a valid timestamp means no prompt and no cost. Otherwise the empty
+// answer is a failed attempt as far as sudo's log and PAM go, faillock
+// included, which is why sudo_prompt_text asks only until it has the prompt.
+// A passprompt from sudoers is displaced by -p, so it's read from there when
+// the file is readable.
+fn probe_prompt(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+) -> Result<Option<String>, String> {
+    let locale = inherited_locale();
+    let env: Vec<(&str, &str)> = locale
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let args = ["-S", "-p", sudoers::PROMPT_PROBE, "-v"];
+    let output = runner
+        .run_with_env(backend.program(), &args, &env, Some(b""))
+        .map_err(|e| format!("Failed to run sudo: {}", e))?;
+    if output.status.success() {
+        return Ok(None);
+    }
+
+    // A lecture=once sudo may lecture ahead of the prompt
+    let (_, stderr) = sudoers::split_lecture(&String::from_utf8_lossy(&output.stderr));
+    let passprompt = sudoers::read_config().and_then(|content| sudoers::parse_passprompt(&content));
+    Ok(sudoers::parse_prompt(&stderr, passprompt.as_deref()))
+}
+
+// Authenticates without running anything, so the command the user triggers
+// next finds the cache warm. An already valid timestamp comes back as
+// `cached`; otherwise the password (or the keyring's) is checked just like
+// for fast_sudo, lockout included.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn prime_sudo(
+    password: Option<Zeroizing<String>>,
+    run_as: Option<String>,
+    reason: Option<String>,
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+    passwords: State<'_, SharedPasswordStore>,
+) -> Result<SudoResponse, String> {
+    let backend = backend.get();
+    if let Some(response) = check_backend(backend).or_else(|| check_run_as(run_as.as_deref())) {
+        return Ok(response);
+    }
+    // Their dialog comes up for every command, there's nothing to keep warm
+    if backend.handles_authentication() {
+        let message = format!("{} authenticates each command itself", backend.program());
+        return Ok(SudoResponse {
+            success: false,
+            output: String::new(),
+            error: Some(message.clone()),
+            error_kind: Some(SudoError::Backend(message)),
+            cached: false,
+            needs_password: false,
+            ..Default::default()
+        });
+    }
+
+    let mut request = SudoRequest {
+        password,
+        run_as,
+        reason,
+        ..Default::default()
+    };
+    let key = CacheKey::new(backend, get_current_user_id(), request.run_as.as_deref());
+    let authorized = authorize_streamed(
+        &runner,
+        &passwords,
+        backend,
+        &cache,
+        &config,
+        &mut request,
+        &key,
+    )
+    .await;
+    Ok(match authorized {
+        Ok((cached, auth_duration_ms)) => SudoResponse {
+            success: true,
+            output: String::new(),
+            error: None,
+            cached,
+            needs_password: false,
+            auth_duration_ms,
+            ..Default::default()
+        },
+        Err(response) => *response,
+    })
+}
+
+// Stops a fast_sudo or fast_sudo_stream run by its request id, along with
+// anything it spawned. Returns false when nothing is running under that id.
+#[tauri::command]
+pub async fn cancel_sudo(
+    request_id: String,
+    running: State<'_, RunningCommands>,
+) -> Result<bool, String> {
+    Ok(running.cancel(&request_id))
+}
+
+// Request ids of the privileged runs still going, for the UI to show or
+// cancel. Runs started without an id can't be listed.
+#[tauri::command]
+pub async fn list_running_sudo(running: State<'_, RunningCommands>) -> Result<Vec<String>, String> {
+    Ok(running.request_ids())
+}
+
+// `systemctl status` for one unit, run like a fast_sudo request (so it needs
+// a valid cache or the password, and a confirmation_token when systemctl is
+// in require_confirmation_for) and picked apart into a UnitStatus. Only the
+// status block is asked for, not the journal lines after it. systemctl exits
+// 3 for a unit that isn't running, which still has a status to parse. A
+// refusal comes back as its SudoError, ConfirmationRequired's token included.
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn sudo_systemctl_status(
+    unit: String,
+    password: Option<Zeroizing<String>>,
+    confirmation_token: Option<String>,
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+    passwords: State<'_, SharedPasswordStore>,
+    running: State<'_, RunningCommands>,
+    limiter: State<'_, RateLimiter>,
+    confirmations: State<'_, Confirmations>,
+) -> Result<UnitStatus, SudoError> {
+    let backend = backend.get();
+    if unit.trim().is_empty() {
+        return Err(SudoError::Backend("No unit given".to_string()));
+    }
+    let request = SudoRequest {
+        command: "systemctl".to_string(),
+        args: ["status", "--no-pager", "--lines=0", "--", &unit]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect(),
+        password,
+        confirmation_token,
+        ..Default::default()
+    };
+    let requests = std::slice::from_ref(&request);
+    if let Some(response) = check_rate(&config, &limiter, requests)
+        .or_else(|| check_confirmation(&config, &confirmations, requests))
+    {
+        return Err(refusal(response));
+    }
+
+    let response = run_privileged_with(
+        &runner,
+        &passwords,
+        backend,
+        &cache,
+        &config,
+        &running,
+        request,
+    )
+    .await
+    .map_err(SudoError::Backend)?;
+    if response.error_kind.is_some() {
+        return Err(refusal(response));
+    }
+
+    let mut status = systemctl::parse_status(&unit, &response.output);
+    if status.raw.is_some() {
+        // "Unit foo.service could not be found." only goes to stderr
+        let stderr = response.stderr.as_deref().unwrap_or_default();
+        status.raw = Some(format!("{}{}", response.output, stderr));
+    }
+    Ok(status)
+}
+
+// The reason a response carries, or its message when it has none
+fn refusal(response: SudoResponse) -> SudoError {
+    response
+        .error_kind
+        .unwrap_or_else(|| SudoError::Backend(response.error.unwrap_or_default()))
+}
+
+#[tauri::command] 
+pub async fn clear_sudo_cache(
+    system_kill: Option<bool>,
+    cache: State<'_, SudoCache>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<(), String> {
+    let backend = backend.get();
+    cache.clear_all();
+    
+    // The system timestamp is shared with every terminal the user has open,
+    // so `sudo -k` only runs when asked for
+    if system_kill.unwrap_or(false) {
+        if let Some((program, args)) = backend.invalidate_command() {
+            let _ = runner.run(program, args, None);
+        }
+    }
+        
+    Ok(())
+}
+
+// A "lock now": stops the keepalive, forgets the app's tokens and drops the
+// system timestamp, then checks with `-n true` that it's really gone. False
+// means the backend still runs things without a password, from a NOPASSWD
+// rule or a timestamp the invalidation couldn't reach, and the UI should say
+// so. polkit and UAC keep nothing that could be checked.
+#[tauri::command]
+pub async fn lock_sudo(
+    cache: State<'_, SudoCache>,
+    keepalive: State<'_, SudoKeepalive>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<bool, String> {
+    let backend = backend.get();
+    keepalive.replace(None);
+    cache.clear_all();
+
+    let Some((program, args)) = backend.invalidate_command() else {
+        return Ok(true);
+    };
+    let output = runner
+        .run(program, args, None)
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    if backend.handles_authentication() {
+        return Ok(true);
+    }
+
+    let key = CacheKey::new(backend, get_current_user_id(), None);
+    let locked = !system_timestamp_valid(runner.as_ref(), backend, &key);
+    if !locked {
+        tracing::warn!("still authenticated after locking");
+    }
+    Ok(locked)
+}
+
+// Switches to another backend without a restart. What the old one granted
+// doesn't carry over, so the app's tokens go and so does the keepalive that
+// was refreshing them. Commands already running finish on the old backend.
+#[tauri::command]
+pub async fn set_privilege_backend(
+    backend: String,
+    active: State<'_, ActiveBackend>,
+    cache: State<'_, SudoCache>,
+    keepalive: State<'_, SudoKeepalive>,
+) -> Result<(), SudoError> {
+    set_privilege_backend_with(&backend, &active, &cache, &keepalive, PrivilegeBackend::installed)
+}
+
+// Same, with the state and the check for whether a backend is installed
+// passed in. Nothing changes when the switch is refused.
+pub fn set_privilege_backend_with(
+    backend: &str,
+    active: &ActiveBackend,
+    cache: &SudoCache,
+    keepalive: &SudoKeepalive,
+    installed: impl Fn(PrivilegeBackend) -> bool,
+) -> Result<(), SudoError> {
+    let Some(requested) = PrivilegeBackend::from_name(backend) else {
+        return Err(SudoError::Backend(format!("Unknown privilege backend: {}", backend)));
+    };
+    if !installed(requested) {
+        return Err(SudoError::NoSudoBinary);
+    }
+
+    let previous = active.set(requested);
+    keepalive.replace(None);
+    cache.clear_all();
+    tracing::info!(from = previous.program(), to = requested.program(), "switched backend");
+    Ok(())
+}
+
+// With system_kill, the system timestamp is only dropped when it belongs to
+// us, other users' sudo state isn't ours to touch
+#[tauri::command]
+pub async fn clear_sudo_cache_for_user(
+    user_id: u32,
+    system_kill: Option<bool>,
+    cache: State<'_, SudoCache>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<(), String> {
+    let backend = backend.get();
+    cache.clear_user(user_id);
+
+    if system_kill.unwrap_or(false) && user_id == get_current_user_id() {
+        if let Some((program, args)) = backend.invalidate_command() {
+            let _ = runner.run(program, args, None);
+        }
+    }
+
+    Ok(())
+}
+
+// Refreshes the system timestamp every `interval_secs` (and the app's token
+// along with it) so a long session never prompts mid-way. Stops on its own as
+// soon as a refresh fails, which means the timestamp has already lapsed.
+#[tauri::command]
+pub async fn start_sudo_keepalive(
+    interval_secs: u64,
+    keepalive: State<'_, SudoKeepalive>,
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<(), String> {
+    let backend = backend.get();
+    let args = backend
+        .refresh_args()
+        .ok_or_else(|| format!("{} has no timestamp to keep fresh", backend.program()))?;
+    if interval_secs == 0 {
+        return Err("Keepalive interval must be at least one second".to_string());
+    }
+
+    let runner = runner.inner().clone();
+    let cache = cache.inner().clone();
+    let key = CacheKey::new(backend, get_current_user_id(), None);
+    let timeout_minutes = config.timeout_minutes;
+
+    let task = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+
+            let runner = runner.clone();
+            let refreshed =
+                tokio::task::spawn_blocking(move || runner.run(backend.program(), args, None))
+                    .await
+                    .is_ok_and(|result| result.is_ok_and(|output| output.status.success()));
+            if !refreshed {
+                tracing::info!("sudo timestamp lapsed, stopping keepalive");
+                break;
+            }
+
+            cache.refresh(&key, timeout_minutes);
+        }
+    });
+
+    keepalive.replace(Some(task));
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn stop_sudo_keepalive(keepalive: State<'_, SudoKeepalive>) -> Result<(), String> {
+    keepalive.replace(None);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn sudo_cache_status(
+    cache: State<'_, SudoCache>,
+    backend: State<'_, ActiveBackend>,
+) -> Result<SudoCacheStatus, String> {
+    let backend = backend.get();
+    let remaining = cache.remaining(&CacheKey::new(backend, get_current_user_id(), None));
+
+    Ok(SudoCacheStatus {
+        authenticated: remaining.is_some(),
+        remaining_secs: remaining.map(|remaining| remaining.as_secs()),
+    })
+}
+
+#[tauri::command]
+pub async fn list_sudo_sessions(cache: State<'_, SudoCache>) -> Result<Vec<SessionInfo>, String> {
+    Ok(cache.sessions())
+}
+
+// Checks a log written with SudoConfig.audit_signing for tampering
+#[tauri::command]
+pub async fn verify_audit_log(
+    path: PathBuf,
+    config: State<'_, SudoConfig>,
+) -> Result<audit::AuditVerification, String> {
+    audit::verify(&config, &path)
+}
+
+// Whether a command would be found once escalated, so a password isn't spent
+// on a typo. With a cached session, root is asked with `-n which`, which
+// searches its secure_path; otherwise, or if that draws a blank, it's looked
+// up locally in $PATH and the usual secure_path. Never prompts.
+#[tauri::command]
+pub async fn can_run(
+    command: String,
+    cache: State<'_, SudoCache>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<bool, String> {
+    let backend = backend.get();
+    if command.trim().is_empty() {
+        return Ok(false);
+    }
+
+    let key = CacheKey::new(backend, get_current_user_id(), None);
+    let bare_name = !command.contains('/');
+    if bare_name && !backend.handles_authentication() && cache.is_authenticated(&key) {
+        let found = runner
+            .run(backend.program(), &["-n", "which", &command], None)
+            .is_ok_and(|output| output.status.success());
+        if found {
+            return Ok(true);
+        }
+    }
+    Ok(policy::resolve_command(&command).is_some())
+}
+
+// Whether the user could escalate at all, going by the group database alone
+// so that nothing runs or prompts. Rules naming the user directly in
+// sudoers don't show up here; check_sudo_privileges asks sudo itself.
+#[tauri::command]
+pub async fn user_can_sudo() -> Result<bool, String> {
+    Ok(in_admin_group())
+}
+
+// Groups that sudoers and doas.conf grant admin rights to out of the box:
+// sudo on Debian and Ubuntu, wheel on Fedora, Arch and the BSDs, admin on macOS
+#[cfg(unix)]
+const ADMIN_GROUPS: [&str; 3] = ["sudo", "wheel", "admin"];
+
+#[cfg(unix)]
+fn in_admin_group() -> bool {
+    use nix::unistd::{getuid, Group, User};
+
+    let uid = getuid();
+    if uid.is_root() {
+        return true;
+    }
+    let user = match User::from_uid(uid) {
+        Ok(Some(user)) => user,
+        _ => return false,
+    };
+    // Membership through the primary group isn't listed in the group's members
+    ADMIN_GROUPS.iter().any(|name| match Group::from_name(name) {
+        Ok(Some(group)) => group.gid == user.gid || group.mem.contains(&user.name),
+        _ => false,
+    })
+}
+
+// UAC takes an administrator's credentials from anyone
+#[cfg(not(unix))]
+fn in_admin_group() -> bool {
+    true
+}
+
+#[tauri::command]
+pub async fn check_sudo_privileges(
+    config: State<'_, SudoConfig>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<PrivilegeStatus, String> {
+    let backend = backend.get();
+    // Under UAC this means the app is already running elevated
+    if backend == PrivilegeBackend::Uac {
+        return Ok(if uac::is_elevated() {
+            PrivilegeStatus::Available
+        } else {
+            PrivilegeStatus::NeedsPassword
+        });
+    }
+
+    // polkit has no non-interactive check at all, its dialog always decides
+    if backend.handles_authentication() {
+        return Ok(if backend.is_installed() {
+            PrivilegeStatus::NeedsPassword
+        } else {
+            PrivilegeStatus::NoSudoBinary
+        });
+    }
+
+    let output = match runner.run(backend.program(), &["-n", "true"], None) {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(PrivilegeStatus::NoSudoBinary)
+        }
+        Err(e) => return Err(format!("Failed to check privileges: {}", e)),
+    };
+
+    if output.status.success() {
+        // With caching disabled every request prompts, regardless of the
+        // system timestamp
+        return Ok(if config.timeout_minutes == 0 {
+            PrivilegeStatus::NeedsPassword
+        } else {
+            PrivilegeStatus::Available
+        });
+    }
+
+    // SystemRunner runs under the C locale, so these messages are stable.
+    // The first two are sudo's, the last is doas's.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let refused = ["is not in the sudoers file", "may not run sudo", "Operation not permitted"];
+    if refused.iter().any(|message| stderr.contains(message)) {
+        Ok(PrivilegeStatus::NotInSudoers)
+    } else {
+        Ok(PrivilegeStatus::NeedsPassword)
+    }
+}
+
+// The rules `sudo -l` reports for the current user, one per entry. Never
+// prompts: when sudo wants a password first, that comes back as an error.
+#[tauri::command]
+pub async fn list_sudo_permissions(
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<Vec<String>, String> {
+    let backend = backend.get();
+    if !backend.supports_login_shell() {
+        return Err(format!("{} can't list permissions", backend.program()));
+    }
+
+    let output = runner
+        .run(backend.program(), &["-n", "-l"], None)
+        .map_err(|e| format!("Failed to list permissions: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() || sudoers::not_allowed(&stdout) || sudoers::not_allowed(&stderr) {
+        return Ok(sudoers::parse_permissions(&stdout));
+    }
+
+    if stderr.contains("password is required") {
+        Err("Password required to list permissions".to_string())
+    } else {
+        Err(format!("Failed to list permissions: {}", stderr.trim()))
+    }
+}
+
+// One snapshot of the privilege subsystem for support. Runs nothing, so it
+// can't prompt or touch sudo's timestamp.
+#[tauri::command]
+pub async fn sudo_diagnostics(
+    cache: State<'_, SudoCache>,
+    config: State<'_, SudoConfig>,
+    keepalive: State<'_, SudoKeepalive>,
+    backend: State<'_, ActiveBackend>,
+) -> Result<SudoDiagnostics, String> {
+    let backend = backend.get();
+    Ok(SudoDiagnostics {
+        backend,
+        backend_installed: backend.installed(),
+        sudo_version: SUDO_VERSION.get().map(|version| version.raw.clone()),
+        timeout_minutes: config.timeout_minutes,
+        sessions: cache.sessions(),
+        keepalive_running: keepalive.is_running(),
+        failed_attempts: cache.failure_counts(),
+    })
+}
+
+// Checks the backend works without changing anything: nothing runs that
+// could prompt, and `true` only runs on a session that's already cached
+#[tauri::command]
+pub async fn sudo_self_test(
+    cache: State<'_, SudoCache>,
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<SudoSelfTest, String> {
+    let backend = backend.get();
+    let installed = backend.is_installed();
+    let mut test = SudoSelfTest {
+        backend,
+        installed,
+        identified: None,
+        version: None,
+        cached_run: None,
+    };
+    if !installed {
+        return Ok(test);
+    }
+
+    let expected: &[&str] = match backend {
+        PrivilegeBackend::Sudo => &["Sudo version", "sudo-rs"],
+        PrivilegeBackend::Pkexec => &["pkexec version"],
+        _ => &[],
+    };
+    if !expected.is_empty() {
+        let flag = if backend == PrivilegeBackend::Sudo { "-V" } else { "--version" };
+        let version = runner
+            .run(backend.program(), &[flag], None)
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                stdout.lines().next().map(|line| line.trim().to_string())
+            });
+        test.identified = Some(version.as_deref().is_some_and(|version| {
+            expected.iter().any(|name| version.starts_with(name))
+        }));
+        test.version = version;
+    }
+
+    let key = CacheKey::new(backend, get_current_user_id(), None);
+    if !backend.handles_authentication() && cache.is_authenticated(&key) {
+        test.cached_run = Some(sudo_timestamp_valid(runner.as_ref(), backend));
+    }
+    Ok(test)
+}
+
+// The installed sudo's version, from `sudo -V`. Fails for other backends.
+#[tauri::command]
+pub async fn sudo_version(
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<SudoVersion, String> {
+    let backend = backend.get();
+    if backend != PrivilegeBackend::Sudo {
+        return Err(format!("{} is not sudo", backend.program()));
+    }
+    detect_version(runner.as_ref(), backend)
+        .ok_or_else(|| "Unrecognized sudo version".to_string())
+}
+
+static SUDO_VERSION: std::sync::OnceLock<SudoVersion> = std::sync::OnceLock::new();
+
+// Parsed once and remembered. main asks at startup, so the flags sudo_args
+// picks suit the sudo that's installed; until then a current one is assumed.
+pub fn detect_version(
+    runner: &dyn CommandRunner,
+    backend: PrivilegeBackend,
+) -> Option<SudoVersion> {
+    if backend != PrivilegeBackend::Sudo {
+        return None;
+    }
+    if let Some(version) = SUDO_VERSION.get() {
+        return Some(version.clone());
+    }
+
+    let output = runner.run(backend.program(), &["-V"], None).ok()?;
+    let version = sudoers::parse_version(&String::from_utf8_lossy(&output.stdout))?;
+    Some(SUDO_VERSION.get_or_init(|| version).clone())
+}
+
+#[tauri::command]
+pub async fn detect_sudo_timeout(
+    backend: State<'_, ActiveBackend>,
+    runner: State<'_, SharedRunner>,
+) -> Result<Option<u64>, String> {
+    let backend = backend.get();
+    Ok(detect_timeout(runner.as_ref(), backend))
+}
+
+// The timestamp_timeout sudo is configured with, in minutes, so the cache
+// can expire when sudo's own timestamp does. `sudo -n -l` lists it only once
+// the user has authenticated (or needs no password), so the sudoers files
+// are tried too, though they're normally only readable by root. None when
+// neither says, which with sudo's defaults means 15.
+pub fn detect_timeout(runner: &dyn CommandRunner, backend: PrivilegeBackend) -> Option<u64> {
+    if backend != PrivilegeBackend::Sudo {
+        return None;
+    }
+
+    // sudo logs (and may mail root about) `-l` from users it has no rules
+    // for, so only ask users who are likely to have some
+    let listed = in_admin_group()
+        .then(|| runner.run(backend.program(), &["-n", "-l"], None).ok())
+        .flatten()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+    listed
+        .and_then(|output| sudoers::parse_timestamp_timeout(&output))
+        .or_else(|| sudoers::parse_timestamp_timeout(&sudoers::read_config()?))
+}
+
+// Saves the password in the OS keyring for use_keyring. It isn't checked
+// here: a wrong one fails (and counts towards lockout) on first use.
+#[tauri::command]
+pub async fn store_sudo_password(
+    password: Zeroizing<String>,
+    passwords: State<'_, SharedPasswordStore>,
+) -> Result<(), String> {
+    passwords.set(&password)
+}
+
+#[tauri::command]
+pub async fn clear_sudo_password(passwords: State<'_, SharedPasswordStore>) -> Result<(), String> {
+    passwords.delete()
+}
+
+// Checks proposed sudoers content before the frontend writes it anywhere
+#[tauri::command]
+pub async fn validate_sudoers(
+    content: String,
+    runner: State<'_, SharedRunner>,
+) -> Result<ValidationResult, String> {
+    sudoers::validate(runner.as_ref(), &content)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[tauri::command]
+pub async fn direct_privilege_escalation(
+    command: String,
+    args: Vec<String>,
+    env: Option<HashMap<String, String>>,
+    cwd: Option<PathBuf>,
+    timeout_secs: Option<u64>,
+    confirmation_token: Option<String>,
+    config: State<'_, SudoConfig>,
+    limiter: State<'_, RateLimiter>,
+    confirmations: State<'_, Confirmations>,
+) -> Result<SudoResponse, String> {
+    // Only what the rate limit and confirmation look at
+    let request = SudoRequest {
+        command: command.clone(),
+        args: args.clone(),
+        cwd: cwd.clone(),
+        confirmation_token,
+        ..Default::default()
+    };
+    let requests = std::slice::from_ref(&request);
+    if let Some(response) = check_cwd(cwd.as_deref())
+        .or_else(|| check_command(&command, cwd.as_deref(), false))
+        .or_else(|| check_permitted(&config, &command, &args, cwd.as_deref()))
+        .or_else(|| check_rate(&config, &limiter, requests))
+        .or_else(|| check_confirmation(&config, &confirmations, requests))
+    {
+        return Ok(response);
+    }
+
+    // Prefer escalating without sudo: run as-is when the app already has
+    // root, otherwise go through polkit. Sudo is only the last resort, and
+    // `method` tells the caller which one actually ran.
+    let escalation = if is_elevated() {
+        None
+    } else if PrivilegeBackend::Uac.is_installed() {
+        Some(PrivilegeBackend::Uac)
+    } else if PrivilegeBackend::Pkexec.is_installed() {
+        Some(PrivilegeBackend::Pkexec)
+    } else if PrivilegeBackend::Sudo.is_installed() {
+        Some(PrivilegeBackend::Sudo)
+    } else {
+        return Err("No privilege escalation mechanism available".to_string());
+    };
+    let escalation_warning = (escalation == Some(PrivilegeBackend::Sudo)).then(|| {
+        let preferred = if cfg!(windows) { "UAC" } else { "polkit" };
+        format!("{} unavailable, used sudo", preferred)
+    });
+    if let Some(warning) = &escalation_warning {
+        tracing::warn!(%warning, "privilege escalation fell back");
+    }
+
+    if escalation == Some(PrivilegeBackend::Uac) {
+        let started = Instant::now();
+        let timeout = config.uac_timeout_secs.map(Duration::from_secs);
+        let outcome = run_uac_blocking(command.clone(), args.clone(), cwd.clone(), timeout).await?;
+        let mut response = uac_response(outcome);
+        response.duration_ms = started.elapsed().as_millis() as u64;
+        let audited = policy::display_command(&command, cwd.as_deref());
+        audit::record(&config, get_current_user_id(), &audited, &args, &response);
+        return Ok(response);
+    }
+
+    let mut cmd = match escalation {
+        Some(backend) => {
+            let mut cmd = tokio::process::Command::new(backend.program());
+            // Nothing here can answer a prompt, so sudo fails rather than
+            // asking on the terminal
+            if backend == PrivilegeBackend::Sudo {
+                cmd.arg("-n");
+            }
+            if backend.supports_preserve_env() {
+                cmd.args(preserve_env_flag(env.as_ref(), &[]));
+            }
+            cmd.arg(&command);
+            cmd
+        }
+        None => tokio::process::Command::new(&command),
+    };
+    cmd.args(&args)
+        .envs(env.iter().flatten())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    if let Some(cwd) = &cwd {
+        cmd.current_dir(cwd);
+    }
+    runner::spawn_in_new_session(cmd.as_std_mut());
+
+    let executed_command = std::iter::once(cmd.as_std().get_program())
+        .chain(cmd.as_std().get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    // A polkit dialog nobody answers is held to the same limit as UAC's
+    let timeout_secs = match escalation {
+        Some(PrivilegeBackend::Pkexec) => timeout_secs.or(config.uac_timeout_secs),
+        _ => timeout_secs,
+    };
+
+    let started = Instant::now();
+    let mut child = spawn_with_retries(config.spawn_retries, || cmd.spawn())
+        .await
+        .map_err(|e| format!("Failed to execute command: {}", e))?;
+    let pipes = ChildPipes {
+        stdout: child.stdout.take(),
+        stderr: child.stderr.take(),
+        ..Default::default()
+    };
+    let finished = wait_for_child(child, pipes, &[], None, timeout_secs, None).await?;
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    let stdout = String::from_utf8_lossy(&finished.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&finished.stderr).to_string();
+    let method = escalation.map_or("direct", PrivilegeBackend::program).to_string();
+    let response = match finished.status {
+        Ok(status) => {
+            let (exit_code, signal) = exit_details(&status);
+            let backend_error = escalation.and_then(|backend| backend.describe_exit(exit_code));
+            let error = match backend_error {
+                Some(reason) => Some(reason.to_string()),
+                None if stderr.is_empty() => None,
+                None => Some(stderr),
+            };
+            SudoResponse {
+                success: exit_code == Some(0),
+                output: stdout,
+                error,
+                error_kind: backend_error.map(|reason| SudoError::Backend(reason.to_string())),
+                cached: false,
+                needs_password: false,
+                exit_code,
+                signal,
+                method,
+                duration_ms,
+                executed_command,
+                escalation_warning,
+                ..Default::default()
+            }
+        }
+        // Nothing can cancel this run, so it timed out
+        Err(reason) => SudoResponse {
+            success: false,
+            output: stdout,
+            error: Some("timed out".to_string()),
+            error_kind: Some(reason),
+            cached: false,
+            needs_password: false,
+            method,
+            duration_ms,
+            executed_command,
+            escalation_warning,
+            ..Default::default()
+        },
+    };
+
+    let audited = policy::display_command(&command, cwd.as_deref());
+    audit::record(&config, get_current_user_id(), &audited, &args, &response);
+
+    Ok(response)
+}
+
+// A `sudo ...` line taken apart. Options other than the target user are
+// kept in `flags` as written, with their values, in order.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParsedSudoCommand {
+    pub run_as: Option<String>,
+    pub env: Vec<(String, String)>,
+    pub flags: Vec<String>,
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+// Short options that take a value, either attached (`-ubob`) or as the next
+// word (`-u bob`)
+const SUDO_VALUE_OPTIONS: &str = "CDghprRtTuU";
+const SUDO_LONG_VALUE_OPTIONS: [&str; 11] = [
+    "--close-from",
+    "--chdir",
+    "--group",
+    "--host",
+    "--prompt",
+    "--role",
+    "--chroot",
+    "--type",
+    "--command-timeout",
+    "--user",
+    "--other-user",
+];
+
+// Utility function to parse sudo commands. Leading options and `VAR=value`
+// assignments are split off the command, so `sudo -u bob -E FOO=1 make`
+// runs `make`; `--` ends the options.
+pub fn parse_sudo_command(input: &str) -> Option<ParsedSudoCommand> {
+    // Split like a POSIX shell so quoted and escaped arguments stay whole.
+    // Unbalanced quotes don't parse at all.
+    let parts = shlex::split(input)?;
+    let mut parts = parts.into_iter();
+    if parts.next()? != "sudo" {
+        return None;
+    }
+
+    let mut parsed = ParsedSudoCommand::default();
+    let mut command = None;
+    while let Some(part) = parts.next() {
+        if part == "--" {
+            break;
+        }
+        if let Some(long) = part.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (format!("--{}", name), Some(value.to_string())),
+                None => (part.clone(), None),
+            };
+            let value = match value {
+                Some(value) => Some(value),
+                None if SUDO_LONG_VALUE_OPTIONS.contains(&name.as_str()) => Some(parts.next()?),
+                None => None,
+            };
+            push_sudo_option(&mut parsed, name, value);
+        } else if part.len() > 1 && part.starts_with('-') {
+            let cluster = &part[1..];
+            for (i, flag) in cluster.char_indices() {
+                if !SUDO_VALUE_OPTIONS.contains(flag) {
+                    push_sudo_option(&mut parsed, format!("-{}", flag), None);
+                    continue;
+                }
+                let attached = &cluster[i + flag.len_utf8()..];
+                let value = if attached.is_empty() {
+                    parts.next()?
+                } else {
+                    attached.to_string()
+                };
+                push_sudo_option(&mut parsed, format!("-{}", flag), Some(value));
+                break;
+            }
+        } else {
+            command = Some(part);
+            break;
+        }
+    }
+
+    // Assignments sit between the options and the command
+    let mut command = match command {
+        Some(command) => command,
+        None => parts.next()?,
+    };
+    while let Some((name, value)) = env_assignment(&command) {
+        parsed.env.push((name.to_string(), value.to_string()));
+        command = parts.next()?;
+    }
+
+    parsed.command = command;
+    parsed.args = parts.collect();
+    Some(parsed)
+}
+
+fn push_sudo_option(parsed: &mut ParsedSudoCommand, name: String, value: Option<String>) {
+    if name == "-u" || name == "--user" {
+        parsed.run_as = value;
+        return;
+    }
+    parsed.flags.push(name);
+    parsed.flags.extend(value);
+}
+
+// `NAME=value` with a name a shell would accept
+fn env_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|(name, _)| {
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+mod base64_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&STANDARD.encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|encoded| STANDARD.decode(encoded).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: u32 = 1000;
+
+    fn ago(secs: u64) -> Instant {
+        Instant::now()
+            .checked_sub(Duration::from_secs(secs))
+            .expect("machine has been up a few minutes")
+    }
+
+    fn sudo_key() -> CacheKey {
+        CacheKey::new(PrivilegeBackend::Sudo, UID, None)
+    }
+
+    fn request(command: &str) -> SudoRequest {
+        SudoRequest {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn with_password(password: &str) -> SudoRequest {
+        SudoRequest {
+            password: Some(Zeroizing::new(password.to_string())),
+            ..request("true")
+        }
+    }
+
+    #[test]
+    fn cache_keys_keep_backends_and_target_users_apart() {
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 15, None, None);
+
+        assert!(cache.is_authenticated(&sudo_key()));
+        assert!(cache.is_authenticated(&CacheKey::new(PrivilegeBackend::Sudo, UID, Some("root"))));
+        assert!(!cache.is_authenticated(&CacheKey::new(PrivilegeBackend::Doas, UID, None)));
+        assert!(!cache.is_authenticated(&CacheKey::new(
+            PrivilegeBackend::Sudo,
+            UID,
+            Some("www-data")
+        )));
+        assert!(!cache.is_authenticated(&CacheKey::new(PrivilegeBackend::Sudo, UID + 1, None)));
+    }
+
+    #[test]
+    fn clear_user_drops_every_target() {
+        let cache = SudoCache::new();
+        let www = CacheKey::new(PrivilegeBackend::Sudo, UID, Some("www-data"));
+        let other = CacheKey::new(PrivilegeBackend::Sudo, UID + 1, None);
+        for key in [&sudo_key(), &www, &other] {
+            cache.authenticate(key, 15, None, None);
+        }
+
+        cache.clear_user(UID);
+        assert!(!cache.is_authenticated(&sudo_key()));
+        assert!(!cache.is_authenticated(&www));
+        assert!(cache.is_authenticated(&other));
+    }
+
+    #[test]
+    fn tokens_expire_after_their_timeout() {
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 0, None, None);
+        assert!(!cache.is_authenticated(&sudo_key()));
+
+        let token = AuthToken::new(UID, ago(120), 1, None, None);
+        cache.tokens.lock().unwrap().insert(sudo_key(), token);
+        assert!(!cache.is_authenticated(&sudo_key()));
+        assert!(cache.tokens.lock().unwrap().is_empty());
+
+        let remaining = {
+            cache.authenticate(&sudo_key(), 15, None, None);
+            cache.remaining(&sudo_key()).unwrap()
+        };
+        assert!(remaining > Duration::from_secs(14 * 60) && remaining <= cache_timeout(15));
+    }
+
+    #[test]
+    fn clear_expired_keeps_live_tokens() {
+        let cache = SudoCache::new();
+        let www = CacheKey::new(PrivilegeBackend::Sudo, UID, Some("www-data"));
+        cache.authenticate(&sudo_key(), 15, None, None);
+        let expired = AuthToken::new(UID, ago(120), 1, None, None);
+        cache.tokens.lock().unwrap().insert(www.clone(), expired);
+
+        cache.clear_expired();
+        let tokens = cache.tokens.lock().unwrap();
+        assert!(tokens.contains_key(&sudo_key()));
+        assert!(!tokens.contains_key(&www));
+    }
+
+    #[test]
+    fn refresh_stays_within_the_session_cap() {
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 15, Some(60), None);
+        assert!(cache.remaining(&sudo_key()).unwrap() <= Duration::from_secs(60));
+
+        let token = AuthToken::new(UID, ago(50), 15, Some(60), None);
+        cache.tokens.lock().unwrap().insert(sudo_key(), token);
+        cache.refresh(&sudo_key(), 15);
+        assert!(cache.remaining(&sudo_key()).unwrap() <= Duration::from_secs(10));
+
+        // Expired tokens aren't revived
+        let expired = AuthToken::new(UID, ago(120), 1, None, None);
+        cache.tokens.lock().unwrap().insert(sudo_key(), expired);
+        cache.refresh(&sudo_key(), 15);
+        assert!(!cache.is_authenticated(&sudo_key()));
+    }
+
+    #[test]
+    fn tokens_go_stale_halfway() {
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 1, None, None);
+        assert!(!cache.is_stale(&sudo_key()));
+
+        let token = AuthToken::new(UID, ago(40), 1, None, None);
+        cache.tokens.lock().unwrap().insert(sudo_key(), token);
+        assert!(cache.is_stale(&sudo_key()));
+    }
+
+    #[test]
+    fn seeds_the_cache_from_a_valid_system_timestamp() {
+        let cache = SudoCache::new();
+        let config = SudoConfig::default();
+        let www = CacheKey::new(PrivilegeBackend::Sudo, UID, Some("www-data"));
+        let runner = MockRunner::new().reply(0, "", "").reply(1, "", "");
+
+        assert!(seed_from_system(&runner, &cache, &sudo_key(), &config, PrivilegeBackend::Sudo));
+        assert!(!seed_from_system(&runner, &cache, &www, &config, PrivilegeBackend::Sudo));
+        assert!(cache.is_authenticated(&sudo_key()));
+        assert!(!cache.is_authenticated(&www));
+        assert_eq!(
+            runner.calls(),
+            [vec!["sudo", "-n", "true"], vec!["sudo", "-n", "-u", "www-data", "true"]]
+        );
+    }
+
+    #[test]
+    fn never_seeds_a_capped_or_uncached_session() {
+        let cache = SudoCache::new();
+        let runner = MockRunner::new();
+        for config in [
+            SudoConfig {
+                max_session_secs: Some(60),
+                ..Default::default()
+            },
+            SudoConfig {
+                timeout_minutes: 0,
+                ..Default::default()
+            },
+        ] {
+            let backend = PrivilegeBackend::Sudo;
+            assert!(!seed_from_system(&runner, &cache, &sudo_key(), &config, backend));
+        }
+        assert!(runner.calls().is_empty());
+        assert!(!cache.is_authenticated(&sudo_key()));
+    }
+
+    #[test]
+    fn stale_tokens_are_checked_against_sudo() {
+        let cache = SudoCache::new();
+        let runner = MockRunner::new().reply(0, "", "").reply(1, "", "");
+        cache.authenticate(&sudo_key(), 1, None, None);
+        assert!(confirm_cached(&runner, &cache, &sudo_key(), PrivilegeBackend::Sudo));
+        assert!(runner.calls().is_empty());
+
+        let stale = || AuthToken::new(UID, ago(40), 1, None, None);
+        cache.tokens.lock().unwrap().insert(sudo_key(), stale());
+        assert!(confirm_cached(&runner, &cache, &sudo_key(), PrivilegeBackend::Sudo));
+        assert!(cache.is_authenticated(&sudo_key()));
+
+        cache.tokens.lock().unwrap().insert(sudo_key(), stale());
+        assert!(!confirm_cached(&runner, &cache, &sudo_key(), PrivilegeBackend::Sudo));
+        assert!(!cache.is_authenticated(&sudo_key()));
+        assert_eq!(runner.calls().len(), 2);
+    }
+
+    #[test]
+    fn asks_for_a_password_when_none_came() {
+        let cache = SudoCache::new();
+        let config = SudoConfig::default();
+        // `sudo -n -l` lists no NOPASSWD rules
+        let runner = MockRunner::new().reply(1, "", "sudo: a password is required\n");
+        let mut request = request("true");
+
+        let response = authenticate_request(
+            &runner,
+            PrivilegeBackend::Sudo,
+            &mut request,
+            &cache,
+            &config,
+            &sudo_key(),
+        )
+        .unwrap();
+        assert_eq!(response.error_kind, Some(SudoError::PasswordRequired));
+        assert!(response.needs_password);
+        assert_eq!(runner.calls(), [["sudo", "-n", "-l"]]);
+    }
+
+    #[test]
+    fn skips_the_password_for_nopasswd_commands() {
+        let cache = SudoCache::new();
+        let config = SudoConfig::default();
+        let listing = concat!(
+            "User alice may run the following commands on host:\n",
+            "    (ALL) NOPASSWD: ALL\n",
+        );
+        let runner = MockRunner::new().reply(0, listing, "");
+        let mut request = request("true");
+
+        let response = authenticate_request(
+            &runner,
+            PrivilegeBackend::Sudo,
+            &mut request,
+            &cache,
+            &config,
+            &sudo_key(),
+        );
+        assert!(response.is_none());
+        assert!(!cache.is_authenticated(&sudo_key()));
+    }
+
+    #[test]
+    fn an_accepted_password_seeds_the_cache() {
+        let cache = SudoCache::new();
+        let config = SudoConfig::default();
+        let runner = MockRunner::new().reply(0, "", "");
+        let mut request = with_password("hunter2");
+
+        let response = authenticate_request(
+            &runner,
+            PrivilegeBackend::Sudo,
+            &mut request,
+            &cache,
+            &config,
+            &sudo_key(),
+        );
+        assert!(response.is_none());
+        assert!(cache.is_authenticated(&sudo_key()));
+        assert!(request.password.is_none());
+        assert_eq!(runner.calls(), [["sudo", "-S", "-v"]]);
+    }
+
+    #[test]
+    fn rejected_passwords_lead_to_a_lockout() {
+        let cache = SudoCache::new();
+        let config = SudoConfig {
+            max_failed_attempts: 1,
+            ..Default::default()
+        };
+        let runner = MockRunner::new().reply(1, "", "Sorry, try again.\n");
+        let authenticate = |password: &str| {
+            authenticate_request(
+                &runner,
+                PrivilegeBackend::Sudo,
+                &mut with_password(password),
+                &cache,
+                &config,
+                &sudo_key(),
+            )
+            .unwrap()
+        };
+
+        let rejected = authenticate("wrong");
+        assert_eq!(rejected.error_kind, Some(SudoError::InvalidPassword));
+        assert!(rejected.needs_password);
+        assert_eq!(cache.failure_counts().get(&UID), Some(&1));
+
+        let locked_out = authenticate("right");
+        assert_eq!(locked_out.error_kind, Some(SudoError::LockedOut));
+        assert_eq!(runner.calls().len(), 1);
+        assert!(!cache.is_authenticated(&sudo_key()));
+    }
+
+    #[test]
+    fn account_problems_are_not_wrong_passwords() {
+        let cache = SudoCache::new();
+        let config = SudoConfig::default();
+        let runner = MockRunner::new().reply(1, "", "sudo: Account or password is expired\n\
+            sudo: your password has expired\n");
+
+        let response = authenticate_request(
+            &runner,
+            PrivilegeBackend::Sudo,
+            &mut with_password("hunter2"),
+            &cache,
+            &config,
+            &sudo_key(),
+        )
+        .unwrap();
+        assert_eq!(response.error_kind, Some(SudoError::PasswordExpired));
+        assert!(!response.needs_password);
+        assert!(cache.failure_counts().is_empty());
+    }
+
+    #[test]
+    fn line_breaks_in_a_password_never_reach_sudo() {
+        let cache = SudoCache::new();
+        let config = SudoConfig::default();
+        let runner = MockRunner::new();
+        for password in ["a\nb", "a\rb"] {
+            let response = authenticate_request(
+                &runner,
+                PrivilegeBackend::Sudo,
+                &mut with_password(password),
+                &cache,
+                &config,
+                &sudo_key(),
+            )
+            .unwrap();
+            assert_eq!(response.error_kind, Some(SudoError::InvalidPassword));
+        }
+        assert!(runner.calls().is_empty());
+    }
+
+    #[test]
+    fn probes_the_prompt_without_dropping_the_timestamp() {
+        let probe = "\u{1e}root\u{1f}alice\u{1f}root\u{1f}box\u{1f}box\u{1e}";
+        let runner = MockRunner::new()
+            .reply(1, "", &format!("{}sudo: no password was provided\n", probe))
+            .reply(1, "", "Geben Sie Ihr Passwort ein: sudo: no password was provided\n")
+            .reply(0, "", "");
+
+        let prompt = probe_prompt(&runner, PrivilegeBackend::Sudo).unwrap();
+        // Unless sudoers happens to be readable here and sets a passprompt
+        let passprompt = sudoers::read_config().and_then(|c| sudoers::parse_passprompt(&c));
+        if passprompt.is_none() {
+            assert_eq!(prompt.as_deref(), Some("[sudo] password for root:"));
+        }
+        assert_eq!(
+            probe_prompt(&runner, PrivilegeBackend::Sudo).unwrap().as_deref(),
+            Some("Geben Sie Ihr Passwort ein:")
+        );
+        assert_eq!(probe_prompt(&runner, PrivilegeBackend::Sudo).unwrap(), None);
+
+        for call in runner.calls() {
+            let at = call.iter().position(|word| word == "sudo").unwrap();
+            assert_eq!(call[at..], ["sudo", "-S", "-p", sudoers::PROMPT_PROBE, "-v"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn captures_the_environment_the_command_saw() {
+        let env = "HOME=/root\0FOO=bar\0PATH=/usr/sbin:/usr/bin\0";
+        let runner = MockRunner::new().reply(0, env, "");
+        let shared: SharedRunner = Arc::new(runner);
+        let request = SudoRequest {
+            env: Some(HashMap::from([("FOO".to_string(), "bar".to_string())])),
+            preserve_env: vec!["FOO".to_string()],
+            capture_env: true,
+            ..request("true")
+        };
+
+        let effective_env = captured_env(&shared, PrivilegeBackend::Sudo, &request)
+            .await
+            .unwrap();
+        assert_eq!(effective_env.get("FOO").map(String::as_str), Some("bar"));
+        assert_eq!(effective_env.len(), 3);
+
+        let captured = captured_env(&shared, PrivilegeBackend::Pkexec, &request).await;
+        assert_eq!(captured, None);
+    }
+
+    #[test]
+    fn capture_env_preserves_the_requested_variables() {
+        let runner = MockRunner::new().reply(0, "FOO=bar\0", "");
+        let request = SudoRequest {
+            env: Some(HashMap::from([("FOO".to_string(), "bar".to_string())])),
+            run_as: Some("www-data".to_string()),
+            ..request("true")
+        };
+        capture_env(&runner, PrivilegeBackend::Sudo, &request).unwrap();
+
+        let call = runner.calls().remove(0);
+        assert!(call.contains(&"FOO=bar".to_string()));
+        let at = call.iter().position(|word| word == "sudo").unwrap();
+        assert_eq!(
+            call[at..],
+            ["sudo", "-n", "--preserve-env=FOO", "-u", "www-data", "env", "-0"]
+        );
+    }
+
+    #[test]
+    fn recognizes_account_problems() {
+        assert_eq!(
+            account_problem("pam_faillock: The account is locked due to 3 failed logins."),
+            Some(SudoError::AccountLocked)
+        );
+        assert_eq!(account_problem("Sorry, try again."), None);
+        assert_eq!(
+            backend_account_problem(PrivilegeBackend::Sudo, "account is locked\n"),
+            None
+        );
+        assert_eq!(
+            backend_account_problem(PrivilegeBackend::Sudo, "sudo: Account locked\n"),
+            Some(SudoError::AccountLocked)
+        );
+    }
+
+    #[test]
+    fn in_flight_keys_cover_what_changes_a_run() {
+        let base = InFlightKey::new(&request("true"));
+        assert_eq!(base, InFlightKey::new(&request("true")));
+
+        let variants = [
+            SudoRequest {
+                stdin: Some("input".to_string()),
+                ..request("true")
+            },
+            SudoRequest {
+                cwd: Some(PathBuf::from("/tmp")),
+                ..request("true")
+            },
+            SudoRequest {
+                env: Some(HashMap::from([("FOO".to_string(), "1".to_string())])),
+                ..request("true")
+            },
+            SudoRequest {
+                timeout_secs: Some(5),
+                ..request("true")
+            },
+            with_password("hunter2"),
+        ];
+        for variant in &variants {
+            assert_ne!(base, InFlightKey::new(variant), "{:?}", variant);
+        }
+    }
+
+    #[tokio::test]
+    async fn identical_requests_share_one_run() {
+        let in_flight = InFlightRequests::new();
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let run = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(SudoResponse {
+                success: true,
+                output: "done".to_string(),
+                ..Default::default()
+            })
+        };
+        let key = || InFlightKey::new(&request("true"));
+
+        let (first, second) = tokio::join!(
+            in_flight.coalesce(key(), run()),
+            in_flight.coalesce(key(), run())
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(first.unwrap().output, "done");
+        assert_eq!(second.unwrap().output, "done");
+
+        // Once it's done the next identical call runs again
+        in_flight.coalesce(key(), run()).await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn rate_limits_all_but_dry_runs() {
+        let config = SudoConfig {
+            rate_limit_per_sec: 1,
+            rate_limit_burst: 1,
+            ..Default::default()
+        };
+        let limiter = RateLimiter::new();
+        let dry_run = SudoRequest {
+            dry_run: true,
+            ..request("true")
+        };
+
+        assert!(check_rate(&config, &limiter, &[request("true")]).is_none());
+        assert!(check_rate(&config, &limiter, &[dry_run]).is_none());
+        let limited = check_rate(&config, &limiter, &[request("true")]).unwrap();
+        assert_eq!(limited.error_kind, Some(SudoError::RateLimited));
+    }
+
+    #[test]
+    fn confirmation_tokens_unlock_one_run() {
+        let config = SudoConfig {
+            require_confirmation_for: vec!["/nonexistent/reboot".to_string()],
+            ..Default::default()
+        };
+        let confirmations = Confirmations::new();
+        let reboot = |token: Option<&str>| SudoRequest {
+            confirmation_token: token.map(str::to_string),
+            ..request("/nonexistent/reboot")
+        };
+
+        let response = check_confirmation(&config, &confirmations, &[reboot(None)]).unwrap();
+        let Some(SudoError::ConfirmationRequired(token)) = response.error_kind else {
+            panic!("expected a confirmation token, got {:?}", response.error_kind);
+        };
+        assert!(check_confirmation(&config, &confirmations, &[reboot(Some(&token))]).is_none());
+        assert!(check_confirmation(&config, &confirmations, &[reboot(Some(&token))]).is_some());
+        assert!(check_confirmation(&config, &confirmations, &[request("true")]).is_none());
+    }
+
+    #[tokio::test]
+    async fn library_entry_point_applies_every_check() {
+        let config = SudoConfig {
+            rate_limit_per_sec: 1,
+            rate_limit_burst: 1,
+            require_confirmation_for: vec!["/nonexistent/reboot".to_string()],
+            ..Default::default()
+        };
+        let runner = Arc::new(MockRunner::new());
+        let shared: SharedRunner = runner.clone();
+        let passwords: SharedPasswordStore = Arc::new(MockStore::new());
+        let (cache, running, in_flight) = (
+            SudoCache::new(),
+            RunningCommands::new(),
+            InFlightRequests::new(),
+        );
+        let (limiter, confirmations) = (RateLimiter::new(), Confirmations::new());
+        let run = |request| {
+            fast_sudo_with(
+                &shared,
+                &passwords,
+                PrivilegeBackend::Sudo,
+                &cache,
+                &config,
+                &running,
+                &in_flight,
+                &limiter,
+                &confirmations,
+                request,
+            )
+        };
+
+        let response = run(request("/nonexistent/reboot")).await.unwrap();
+        assert!(matches!(
+            response.error_kind,
+            Some(SudoError::ConfirmationRequired(_))
+        ));
+        let response = run(request("/nonexistent/reboot")).await.unwrap();
+        assert_eq!(response.error_kind, Some(SudoError::RateLimited));
+        assert!(runner.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reads_capped_lines_a_byte_at_a_time() {
+        let input = std::io::Cursor::new("aé\nb\r\nxé€z\né".as_bytes());
+        // One byte per read, so every multi-byte character arrives split
+        let mut reader = tokio::io::BufReader::with_capacity(1, input);
+        let mut line = Vec::new();
+        let mut lines = Vec::new();
+        while read_line_capped(&mut reader, &mut line, 16).await.unwrap() {
+            lines.push(String::from_utf8(line.clone()).unwrap());
+        }
+        assert_eq!(lines, ["aé", "b", "xé€z", "é"]);
+
+        // A cap landing inside a character drops the whole character
+        let input = std::io::Cursor::new("aé\nxé€z\n".as_bytes());
+        let mut reader = tokio::io::BufReader::with_capacity(1, input);
+        assert!(read_line_capped(&mut reader, &mut line, 2).await.unwrap());
+        assert_eq!(line, b"a");
+        assert!(read_line_capped(&mut reader, &mut line, 5).await.unwrap());
+        assert_eq!(line, "xé".as_bytes());
+        assert!(!read_line_capped(&mut reader, &mut line, 5).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn switching_backends_drops_what_the_old_one_granted() {
+        let active = ActiveBackend::new(PrivilegeBackend::Sudo);
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 15, None, None);
+        let keepalive = SudoKeepalive::new();
+        keepalive.replace(Some(tokio::spawn(std::future::pending())));
+        let installed = |backend| backend == PrivilegeBackend::Doas;
+
+        set_privilege_backend_with("doas", &active, &cache, &keepalive, installed).unwrap();
+        assert_eq!(active.get(), PrivilegeBackend::Doas);
+        assert!(!cache.is_authenticated(&sudo_key()));
+        assert!(!keepalive.is_running());
+    }
+
+    #[test]
+    fn refused_backend_switches_change_nothing() {
+        let active = ActiveBackend::new(PrivilegeBackend::Sudo);
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 15, None, None);
+        let keepalive = SudoKeepalive::new();
+        let installed = |backend| backend == PrivilegeBackend::Sudo;
+
+        let unknown = set_privilege_backend_with("su", &active, &cache, &keepalive, installed);
+        assert!(matches!(unknown, Err(SudoError::Backend(_))));
+        let missing = set_privilege_backend_with("doas", &active, &cache, &keepalive, installed);
+        assert_eq!(missing, Err(SudoError::NoSudoBinary));
+        assert_eq!(active.get(), PrivilegeBackend::Sudo);
+        assert!(cache.is_authenticated(&sudo_key()));
+    }
+
+    #[test]
+    fn utf8_boundary_holds_back_a_split_character() {
+        let text = "aé€😀".as_bytes();
+        assert_eq!(utf8_boundary(text), text.len());
+        assert_eq!(utf8_boundary(&text[..2]), 1);
+        assert_eq!(utf8_boundary(&text[..5]), 3);
+        assert_eq!(utf8_boundary(&text[..text.len() - 1]), 6);
+        assert_eq!(utf8_boundary(b""), 0);
+    }
+
+    // Whether a process with this pid is still around, zombies included
+    #[cfg(unix)]
+    fn process_exists(pid: u32) -> bool {
+        nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), None).is_ok()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn kills_and_reaps_a_child_past_its_timeout() {
+        let mut cmd = tokio::process::Command::new("sleep");
+        cmd.arg("30").stdout(Stdio::piped()).stderr(Stdio::piped());
+        runner::spawn_in_new_session(cmd.as_std_mut());
+        let mut child = cmd.spawn().unwrap();
+        let pid = child.id().unwrap();
+        let pipes = ChildPipes {
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let finished = wait_for_child(child, pipes, &[], None, Some(1), None).await.unwrap();
+        assert_eq!(finished.status.unwrap_err(), SudoError::Timeout);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!process_exists(pid));
+    }
+
+    // What the app does on exit, see main
+    #[cfg(unix)]
+    #[test]
+    fn exit_stops_running_commands_and_shuts_the_cache_down_once() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let commands = RunningCommands::new();
+        let running = commands.register(Some("job")).unwrap();
+        let mut cmd = std::process::Command::new("sleep");
+        cmd.arg("30");
+        runner::spawn_in_new_session(&mut cmd);
+        let mut child = cmd.spawn().unwrap();
+        let pid = child.id();
+        running.track(Some(pid));
+        // Reaped as it exits, the way tokio would, so kill_all sees it go
+        let reaper = std::thread::spawn(move || child.wait());
+        let cache = SudoCache::new();
+        cache.authenticate(&sudo_key(), 15, None, None);
+        let runner = MockRunner::new();
+
+        for _ in 0..2 {
+            commands.kill_all();
+            cache.shutdown(&runner, PrivilegeBackend::Sudo);
+        }
+        let status = reaper.join().unwrap().unwrap();
+        assert_eq!(status.signal(), Some(nix::libc::SIGTERM));
+        assert!(!process_exists(pid));
+        assert!(!cache.is_authenticated(&sudo_key()));
+        assert_eq!(runner.calls(), [["sudo", "-k"]]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn cancelling_a_run_kills_and_reaps_it() {
+        let commands = RunningCommands::new();
+        let mut running = commands.register(Some("job")).unwrap();
+        let mut cmd = tokio::process::Command::new("sleep");
+        cmd.arg("30");
+        runner::spawn_in_new_session(cmd.as_std_mut());
+        let child = cmd.spawn().unwrap();
+        let pid = child.id().unwrap();
+        running.track(Some(pid));
+        assert_eq!(commands.pid("job"), Some(pid));
+
+        let started = Instant::now();
+        let (finished, cancelled) = tokio::join!(
+            wait_for_child(child, ChildPipes::default(), &[], None, None, Some(&mut running)),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                commands.cancel("job")
+            }
+        );
+        assert!(cancelled);
+        assert_eq!(finished.unwrap().status.unwrap_err(), SudoError::Cancelled);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(!process_exists(pid));
+
+        drop(running);
+        assert!(commands.request_ids().is_empty());
+        assert!(!commands.cancel("job"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn waits_for_a_child_and_feeds_it_input() {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.args(["-c", "cat; echo oops >&2; exit 4"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = cmd.spawn().unwrap();
+        let pipes = ChildPipes {
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take(),
+            stderr: child.stderr.take(),
+            ..Default::default()
+        };
+
+        let finished = wait_for_child(child, pipes, b"hello", Some(4), None, None).await.unwrap();
+        assert_eq!(finished.status.unwrap().code(), Some(4));
+        assert_eq!(finished.stdout, b"hell");
+        assert_eq!(finished.stderr, b"oops");
+        assert!(finished.truncated);
+    }
+
+    #[tokio::test]
+    async fn retries_transient_spawn_failures() {
+        use std::io::{Error, ErrorKind};
+
+        let mut attempts = 0;
+        let spawned = spawn_with_retries(3, || {
+            attempts += 1;
+            match attempts {
+                1 | 2 => Err(Error::from(ErrorKind::WouldBlock)),
+                _ => Ok(attempts),
+            }
+        })
+        .await;
+        assert_eq!(spawned.unwrap(), 3);
+
+        let mut attempts = 0;
+        let spawned: std::io::Result<()> = spawn_with_retries(2, || {
+            attempts += 1;
+            Err(Error::from(ErrorKind::Interrupted))
+        })
+        .await;
+        assert_eq!(spawned.unwrap_err().kind(), ErrorKind::Interrupted);
+        assert_eq!(attempts, 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_lasting_spawn_failures() {
+        use std::io::{Error, ErrorKind};
+
+        for kind in [ErrorKind::NotFound, ErrorKind::PermissionDenied] {
+            let mut attempts = 0;
+            let spawned: std::io::Result<()> = spawn_with_retries(3, || {
+                attempts += 1;
+                Err(Error::from(kind))
+            })
+            .await;
+            assert_eq!(spawned.unwrap_err().kind(), kind);
+            assert_eq!(attempts, 1);
+        }
+    }
+
+    #[test]
+    fn only_interruptions_are_transient() {
+        use std::io::{Error, ErrorKind};
+        assert!(is_transient(&Error::from(ErrorKind::Interrupted)));
+        assert!(is_transient(&Error::from(ErrorKind::WouldBlock)));
+        assert!(!is_transient(&Error::from(ErrorKind::NotFound)));
+    }
+
+    #[test]
+    fn parses_sudo_command_lines() {
+        let parsed = parse_sudo_command("sudo -u bob -E FOO=1 make 'all tests'").unwrap();
+        assert_eq!(parsed.run_as.as_deref(), Some("bob"));
+        assert_eq!(parsed.flags, ["-E"]);
+        assert_eq!(parsed.env, [("FOO".to_string(), "1".to_string())]);
+        assert_eq!(parsed.command, "make");
+        assert_eq!(parsed.args, ["all tests"]);
+
+        let parsed = parse_sudo_command("sudo -ubob --chdir=/srv -- ls -la").unwrap();
+        assert_eq!(parsed.run_as.as_deref(), Some("bob"));
+        assert_eq!(parsed.flags, ["--chdir", "/srv"]);
+        assert_eq!((parsed.command.as_str(), &parsed.args[..]), ("ls", &["-la".to_string()][..]));
+
+        assert_eq!(parse_sudo_command("ls -la"), None);
+        assert_eq!(parse_sudo_command("sudo -u"), None);
+        assert_eq!(parse_sudo_command("sudo 'unbalanced"), None);
+    }
+}
@@ -0,0 +1,337 @@
+// src-tauri/src/sudo/policy.rs
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Sudo's usual secure_path. Searched after $PATH, since sudo finds commands
+// there that a normal user's PATH often leaves out, like the sbin tools.
+#[cfg(unix)]
+const SECURE_PATH: &[&str] = &[
+    "/usr/local/sbin",
+    "/usr/local/bin",
+    "/usr/sbin",
+    "/usr/bin",
+    "/sbin",
+    "/bin",
+];
+
+// Looks a bare command name up in $PATH the way the shell would. Anything
+// containing a slash is taken as a path as-is.
+pub fn resolve_command(command: &str) -> Option<PathBuf> {
+    if command.is_empty() {
+        return None;
+    }
+
+    if command.contains('/') {
+        return Some(PathBuf::from(command));
+    }
+
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    #[cfg(unix)]
+    let fallback = SECURE_PATH.iter().map(PathBuf::from);
+    #[cfg(not(unix))]
+    let fallback = std::iter::empty();
+    std::env::split_paths(&path)
+        .chain(fallback)
+        .map(|dir| dir.join(command))
+        .find(|candidate| is_executable(candidate))
+}
+
+// The file a command actually runs, with symlinks followed. Relative paths
+// are taken from `cwd` when given, the directory the command will run in.
+// None when there's no such command.
+pub fn canonicalize_command(command: &str, cwd: Option<&Path>) -> Option<PathBuf> {
+    let resolved = resolve_command(command)?;
+    let resolved = match cwd {
+        Some(cwd) if resolved.is_relative() => cwd.join(resolved),
+        _ => resolved,
+    };
+    std::fs::canonicalize(resolved).ok()
+}
+
+// What audit logs show for a command: its canonical path, or the command
+// as given when it can't be resolved
+pub fn display_command(command: &str, cwd: Option<&Path>) -> String {
+    canonicalize_command(command, cwd)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|| command.to_string())
+}
+
+// Entries are resolved the same way as the command, symlinks included, so
+// `systemctl`, `/usr/bin/systemctl` and `/bin/systemctl` on a merged-/usr
+// system all match each other. The names have to agree as well: multi-call
+// binaries like busybox are one file behind many commands, and allowing
+// `ls` mustn't allow `sh`.
+pub fn is_allowed(allowlist: &[String], command: &str, cwd: Option<&Path>) -> bool {
+    let canonical = match canonicalize_command(command, cwd) {
+        Some(canonical) => canonical,
+        None => return false,
+    };
+
+    allowlist.iter().any(|entry| {
+        program_name(entry) == program_name(command)
+            && canonicalize_command(entry, None).as_deref() == Some(canonical.as_path())
+    })
+}
+
+// Like is_allowed, for lists where a miss is the risky outcome, such as
+// SudoConfig.always_prompt: a command that can't be resolved still matches
+// an entry with the same name
+pub fn is_listed(list: &[String], command: &str, cwd: Option<&Path>) -> bool {
+    if canonicalize_command(command, cwd).is_some() {
+        return is_allowed(list, command, cwd);
+    }
+    list.iter().any(|entry| program_name(entry) == program_name(command))
+}
+
+// The label of the longest key the command line starts with. A key is a
+// program name followed by however many leading args it needs:
+//
+//     "systemctl restart nginx" => "Restart the web server"
+//     "apt" => "Manage packages"
+pub fn label_for(
+    labels: &HashMap<String, String>,
+    command: &str,
+    args: &[String],
+) -> Option<String> {
+    labels
+        .iter()
+        .filter_map(|(key, label)| {
+            let mut words = key.split_whitespace();
+            let program = words.next()?;
+            let rest: Vec<&str> = words.collect();
+            let matches = program_name(program) == program_name(command)
+                && rest.len() <= args.len()
+                && rest.iter().zip(args).all(|(word, arg)| word == arg);
+            matches.then_some((rest.len(), label))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, label)| label.clone())
+}
+
+// Shells, and interpreters that run code handed to them on the command line
+const SHELLS: &[&str] = &[
+    "sh", "bash", "dash", "zsh", "ksh", "mksh", "csh", "tcsh", "fish", "busybox", "python",
+    "python3", "perl", "ruby", "node",
+];
+
+// Characters that only mean something to a shell. Args reach the command
+// verbatim, so in one of them they usually point at an attempt to chain
+// commands that slipped past a shell-less check.
+const SHELL_METACHARACTERS: &[char] = &[';', '|', '&', '$', '`', '<', '>', '\n'];
+
+// An arg_validator for SudoConfig that refuses shells and interpreters,
+// including when started through `env`, and args containing shell
+// metacharacters
+pub fn deny_shells(command: &str, args: &[String]) -> bool {
+    if args.iter().any(|arg| arg.contains(SHELL_METACHARACTERS)) {
+        return false;
+    }
+
+    let mut words: Vec<String> = std::iter::once(command.to_string())
+        .chain(args.iter().cloned())
+        .collect();
+    // `env -u FOO VAR=value sh` still starts a shell
+    while words.first().is_some_and(|name| program_name(name) == "env") {
+        match env_command(&words[1..]) {
+            Some(command) => words = command,
+            None => return false,
+        }
+    }
+    !words.first().is_some_and(|name| SHELLS.contains(&program_name(name)))
+}
+
+// The command line `env [OPTION]... [NAME=VALUE]... [COMMAND [ARG]...]`
+// starts, empty when it only prints the environment. The words -S splits
+// its value into are read as if they'd been given in its place. None for an
+// option not known here, since it might take the next word as its value.
+fn env_command(args: &[String]) -> Option<Vec<String>> {
+    let mut words = args.iter();
+    while let Some(word) = words.as_slice().first() {
+        if !word.starts_with('-') {
+            break;
+        }
+        words.next();
+        if word == "--" {
+            break;
+        }
+
+        let (split, attached) = match word.strip_prefix("--") {
+            Some(long) => {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (long, None),
+                };
+                match name {
+                    "ignore-environment" | "null" | "debug" | "list-signal-handling"
+                    | "default-signal" | "ignore-signal" | "block-signal" => continue,
+                    "unset" | "chdir" | "argv0" => (false, value),
+                    "split-string" => (true, value),
+                    _ => return None,
+                }
+            }
+            // A lone `-` is the same as -i
+            None => {
+                let cluster = &word[1..];
+                let Some(at) = cluster.find(|flag| !matches!(flag, 'i' | '0' | 'v')) else {
+                    continue;
+                };
+                let flag = cluster[at..].chars().next()?;
+                if !matches!(flag, 'u' | 'C' | 'S' | 'a' | 'P') {
+                    return None;
+                }
+                let attached = &cluster[at + 1..];
+                (flag == 'S', (!attached.is_empty()).then_some(attached))
+            }
+        };
+        let value = match attached {
+            Some(value) => value,
+            None => words.next()?.as_str(),
+        };
+        if split {
+            let mut expanded = shlex::split(value)?;
+            expanded.extend(words.cloned());
+            return env_command(&expanded);
+        }
+    }
+
+    Some(
+        words
+            .skip_while(|word| word.contains('='))
+            .cloned()
+            .collect(),
+    )
+}
+
+fn program_name(command: &str) -> &str {
+    command.rsplit('/').next().unwrap_or(command)
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    // A directory holding one executable and links to it, like a
+    // multi-call binary
+    #[cfg(unix)]
+    fn fake_bin(name: &str) -> PathBuf {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("term-policy-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let busybox = dir.join("busybox");
+        std::fs::write(&busybox, "").unwrap();
+        std::fs::set_permissions(&busybox, std::fs::Permissions::from_mode(0o755)).unwrap();
+        symlink(&busybox, dir.join("ls")).unwrap();
+        symlink(&busybox, dir.join("sh")).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn allows_listed_commands_through_links() {
+        let dir = fake_bin("allowed");
+        let ls = dir.join("ls").to_string_lossy().into_owned();
+        let sh = dir.join("sh").to_string_lossy().into_owned();
+
+        let allowlist = strings(&[&ls]);
+        assert!(is_allowed(&allowlist, &ls, None));
+        assert!(is_allowed(&allowlist, "./ls", Some(&dir)));
+        // Same file, different name
+        assert!(!is_allowed(&allowlist, &sh, None));
+        assert!(!is_allowed(&allowlist, &dir.join("missing").to_string_lossy(), None));
+        assert!(!is_allowed(&[], &ls, None));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lists_match_unresolvable_commands_by_name() {
+        let dir = fake_bin("listed");
+        let sh = dir.join("sh").to_string_lossy().into_owned();
+        let list = strings(&["/nonexistent/reboot", &sh]);
+
+        assert!(is_listed(&list, "/also/missing/reboot", None));
+        assert!(is_listed(&list, &sh, None));
+        assert!(!is_listed(&list, &dir.join("ls").to_string_lossy(), None));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn labels_the_longest_matching_key() {
+        let labels: HashMap<String, String> = [
+            ("systemctl", "Manage services"),
+            ("systemctl restart nginx", "Restart the web server"),
+        ]
+        .iter()
+        .map(|(key, label)| (key.to_string(), label.to_string()))
+        .collect();
+
+        assert_eq!(
+            label_for(&labels, "/usr/bin/systemctl", &strings(&["restart", "nginx"])).as_deref(),
+            Some("Restart the web server")
+        );
+        assert_eq!(
+            label_for(&labels, "systemctl", &strings(&["restart"])).as_deref(),
+            Some("Manage services")
+        );
+        assert_eq!(label_for(&labels, "apt", &[]), None);
+    }
+
+    #[test]
+    fn denies_shells_and_metacharacters() {
+        assert!(deny_shells("apt", &strings(&["install", "htop"])));
+        assert!(!deny_shells("/bin/bash", &[]));
+        assert!(!deny_shells("python3", &strings(&["-c", "print(1)"])));
+        assert!(!deny_shells("env", &strings(&["-i", "PATH=/bin", "sh"])));
+        assert!(!deny_shells("env", &strings(&["env", "zsh"])));
+        assert!(deny_shells("env", &strings(&["FOO=1", "apt", "update"])));
+        assert!(deny_shells("env", &strings(&["-u", "FOO", "apt", "update"])));
+        assert!(deny_shells("env", &[]));
+        for args in [
+            &["-u", "X", "bash"][..],
+            &["-iu", "X", "bash"],
+            &["-uX", "bash"],
+            &["--unset", "X", "bash"],
+            &["--chdir=/", "sh"],
+            &["-C", "/", "sh"],
+            &["-S", "bash -c id"],
+            &["--split-string=-u X 'ba''sh'"],
+            &["-i", "-S", "FOO=1 env -u X zsh"],
+            &["--", "FOO=1", "sh"],
+            &["--frobnicate", "apt"],
+        ] {
+            assert!(!deny_shells("/usr/bin/env", &strings(args)), "{:?}", args);
+        }
+        for arg in ["a;b", "a|b", "a&b", "$HOME", "`id`", "<in", ">out", "a\nb"] {
+            assert!(!deny_shells("echo", &strings(&[arg])), "{:?}", arg);
+        }
+    }
+
+    #[test]
+    fn paths_are_taken_as_given() {
+        assert_eq!(resolve_command(""), None);
+        assert_eq!(resolve_command("./tool"), Some(PathBuf::from("./tool")));
+        assert_eq!(resolve_command("definitely-not-a-command-here"), None);
+    }
+}
@@ -0,0 +1,50 @@
+// src-tauri/src/sudo/progress.rs
+use serde::{Deserialize, Serialize};
+
+// Package managers whose output fast_sudo_stream knows how to turn into
+// `sudo://progress` events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressKind {
+    Apt,
+    Dnf,
+}
+
+// Percentage (0-100) reported by a line of output, if it's a progress line
+pub fn parse(kind: ProgressKind, line: &str) -> Option<u8> {
+    match kind {
+        ProgressKind::Apt => parse_apt(line),
+        ProgressKind::Dnf => parse_dnf(line),
+    }
+}
+
+// dpkg's fancy progress bar: `Progress: [ 42%] [#####.....]`, usually wrapped
+// in terminal escapes that move the cursor to the bottom line
+fn parse_apt(line: &str) -> Option<u8> {
+    let (_, rest) = line.split_once("Progress: [")?;
+    let (percent, _) = rest.split_once("%]")?;
+    let percent: u8 = percent.trim().parse().ok()?;
+    (percent <= 100).then_some(percent)
+}
+
+// dnf counts packages rather than printing percentages:
+//   (3/12): curl-8.6.0-7.fc40.x86_64.rpm     123 kB/s | 301 kB     00:02
+//   Installing       : curl-8.6.0-7.fc40.x86_64                    3/12
+fn parse_dnf(line: &str) -> Option<u8> {
+    let line = line.trim();
+    let counter = if let Some(rest) = line.strip_prefix('(') {
+        rest.split_once("):")?.0
+    } else if line.contains(" : ") {
+        line.rsplit(char::is_whitespace).next()?
+    } else {
+        return None;
+    };
+
+    let (done, total) = counter.split_once('/')?;
+    let done: u64 = done.parse().ok()?;
+    let total: u64 = total.parse().ok()?;
+    if total == 0 || done > total {
+        return None;
+    }
+    Some((done * 100 / total) as u8)
+}
@@ -0,0 +1,55 @@
+// src-tauri/src/sudo/prompt.rs
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use zeroize::Zeroizing;
+
+// Answers interactive runs are waiting on, one per request id. A run asks
+// with `wait` after emitting `sudo://prompt`; respond_sudo_prompt delivers.
+#[derive(Default, Clone)]
+pub struct PendingPrompts {
+    waiting: Arc<Mutex<HashMap<String, oneshot::Sender<Zeroizing<String>>>>>,
+}
+
+impl PendingPrompts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Resolves with the frontend's answer, or fails if the prompt is
+    // forgotten first. A run only ever has one question open at a time.
+    pub(crate) fn wait(&self, request_id: &str) -> oneshot::Receiver<Zeroizing<String>> {
+        let (sender, receiver) = oneshot::channel();
+        if let Ok(mut waiting) = self.waiting.lock() {
+            waiting.insert(request_id.to_string(), sender);
+        }
+        receiver
+    }
+
+    // False when that request isn't waiting on anything
+    pub fn respond(&self, request_id: &str, response: Zeroizing<String>) -> bool {
+        let sender = match self.waiting.lock() {
+            Ok(mut waiting) => waiting.remove(request_id),
+            Err(_) => None,
+        };
+        sender.is_some_and(|sender| sender.send(response).is_ok())
+    }
+
+    pub(crate) fn forget(&self, request_id: &str) {
+        if let Ok(mut waiting) = self.waiting.lock() {
+            waiting.remove(request_id);
+        }
+    }
+}
+
+// Whether unfinished stderr output is a question. With -S, sudo writes its
+// own prompt and every PAM one (OTP codes, new passwords) to stderr without a
+// newline and then reads a line from stdin:
+//
+//     [sudo] password for alice:
+//     Verification code:
+//
+// so text left hanging after a colon is taken as one.
+pub fn is_prompt(pending: &str) -> bool {
+    pending.trim_end().ends_with(':')
+}
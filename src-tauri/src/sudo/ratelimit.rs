@@ -0,0 +1,92 @@
+// src-tauri/src/sudo/ratelimit.rs
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Token buckets for the execute path, one per uid. Each bucket holds up to
+// `burst` executions and refills at `per_sec`, so a frontend stuck in a loop
+// gets RateLimited instead of spawning a storm of sudo processes.
+#[derive(Default, Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<u32, Bucket>>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Takes `cost` executions from the uid's bucket, or nothing at all when
+    // it doesn't have that many. A `per_sec` of 0 turns the limit off.
+    pub fn try_acquire(&self, user_id: u32, cost: u32, per_sec: u32, burst: u32) -> bool {
+        if per_sec == 0 {
+            return true;
+        }
+        // Only a guard against accidents, so a poisoned lock lets runs through
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let capacity = f64::from(burst.max(1));
+        let bucket = buckets.entry(user_id).or_insert(Bucket {
+            tokens: capacity,
+            refilled: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * f64::from(per_sec)).min(capacity);
+        bucket.refilled = now;
+
+        let cost = f64::from(cost);
+        if bucket.tokens < cost {
+            return false;
+        }
+        bucket.tokens -= cost;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn refuses_once_the_burst_is_spent() {
+        let limiter = RateLimiter::new();
+        assert!(limiter.try_acquire(1000, 2, 1, 3));
+        assert!(limiter.try_acquire(1000, 1, 1, 3));
+        assert!(!limiter.try_acquire(1000, 1, 1, 3));
+        // Other users have their own bucket
+        assert!(limiter.try_acquire(1001, 3, 1, 3));
+    }
+
+    #[test]
+    fn a_refused_acquire_takes_nothing() {
+        let limiter = RateLimiter::new();
+        assert!(!limiter.try_acquire(1000, 5, 1, 3));
+        assert!(limiter.try_acquire(1000, 3, 1, 3));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let limiter = RateLimiter::new();
+        assert!(limiter.try_acquire(1000, 2, 100, 2));
+        assert!(!limiter.try_acquire(1000, 1, 100, 2));
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(limiter.try_acquire(1000, 2, 100, 2));
+    }
+
+    #[test]
+    fn zero_per_sec_is_unlimited() {
+        let limiter = RateLimiter::new();
+        for _ in 0..100 {
+            assert!(limiter.try_acquire(1000, 10, 0, 1));
+        }
+    }
+}
@@ -0,0 +1,306 @@
+// src-tauri/src/sudo/runner.rs
+use std::io::{self, Read, Write};
+use std::process::{Command, Output, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+#[cfg(test)]
+use std::{collections::VecDeque, process::ExitStatus, sync::Mutex};
+
+// Runs the backend's own plumbing: password checks, timestamp probes and
+// invalidation. The elevated command itself is spawned directly, since
+// timeouts, process groups and streaming all need the live child.
+pub trait CommandRunner: Send + Sync {
+    // `stdin` is written in full and then closed
+    fn run(&self, program: &str, args: &[&str], stdin: Option<&[u8]>) -> io::Result<Output> {
+        self.run_with_env(program, args, &[], stdin)
+    }
+
+    // Same, with extra variables set in the child's environment
+    fn run_with_env(
+        &self,
+        program: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+        stdin: Option<&[u8]>,
+    ) -> io::Result<Output> {
+        self.run_with_timeout(program, args, env, stdin, None)
+    }
+
+    // Same again, but once `timeout` passes the child is killed and reaped,
+    // and the call fails with ErrorKind::TimedOut
+    fn run_with_timeout(
+        &self,
+        program: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+        stdin: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Output>;
+}
+
+// What the Tauri commands get from managed state
+pub type SharedRunner = Arc<dyn CommandRunner>;
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    // Sudo's messages are translated, and callers sometimes look at them, so
+    // everything runs under the C locale to keep the text stable
+    fn run_with_timeout(
+        &self,
+        program: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+        stdin: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Output> {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env("LANG", "C")
+            .env("LC_ALL", "C")
+            .envs(env.iter().copied())
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        spawn_in_new_session(&mut command);
+        let mut child = command.spawn()?;
+
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            // Dropping the pipe closes it, so the child sees EOF rather than
+            // waiting on more input
+            if let Err(e) = pipe.write_all(input).and_then(|_| pipe.flush()) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        }
+
+        let timeout = match timeout {
+            // Drains stdout and stderr as well, so neither pipe can fill up and stall
+            None => return child.wait_with_output(),
+            Some(timeout) => timeout,
+        };
+
+        // Same draining, on threads so this one can watch the clock
+        let stdout = child.stdout.take().map(drain);
+        let stderr = child.stderr.take().map(drain);
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                // The readers are left to finish on their own: anything the
+                // child started may still hold the pipes open
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} timed out after {:?}", program, timeout),
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        let collect = |reader: Option<std::thread::JoinHandle<Vec<u8>>>| {
+            reader
+                .and_then(|reader| reader.join().ok())
+                .unwrap_or_default()
+        };
+        Ok(Output {
+            status,
+            stdout: collect(stdout),
+            stderr: collect(stderr),
+        })
+    }
+}
+
+// Has the command start in a session of its own: that means a process group
+// of its own, so one signal (a timeout's or a cancel's) reaches everything it
+// starts, and no controlling terminal, so sudo can never prompt on the one
+// the app was launched from. Does nothing off Unix.
+//
+// The sudo module denies unsafe code; this is the one exception on Unix.
+// std's safe CommandExt::process_group would cover the process group, but
+// not the controlling terminal, which takes setsid in the child.
+#[allow(unsafe_code)]
+pub(crate) fn spawn_in_new_session(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Safety: setsid is async-signal-safe and touches no shared state
+        unsafe { command.pre_exec(new_session) };
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+#[cfg(unix)]
+fn new_session() -> io::Result<()> {
+    nix::unistd::setsid().map(|_| ()).map_err(io::Error::from)
+}
+
+fn drain(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    })
+}
+
+// Stand-in for SystemRunner that never spawns anything. Replies are handed out
+// in the order they were queued; once they run out every call succeeds with
+// no output. Each call is recorded as `VAR=value` env assignments, then the
+// program and its args. Test-only.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockRunner {
+    // Each with how long the pretend command takes
+    replies: Mutex<VecDeque<(Output, Duration)>>,
+    calls: Mutex<Vec<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl MockRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reply(self, exit_code: i32, stdout: &str, stderr: &str) -> Self {
+        self.reply_after(Duration::ZERO, exit_code, stdout, stderr)
+    }
+
+    // A reply that takes `delay` to arrive. Calls with a shorter timeout
+    // wait that long instead and fail with ErrorKind::TimedOut.
+    pub fn reply_after(self, delay: Duration, exit_code: i32, stdout: &str, stderr: &str) -> Self {
+        if let Ok(mut replies) = self.replies.lock() {
+            let output = Output {
+                status: exit_status(exit_code),
+                stdout: stdout.as_bytes().to_vec(),
+                stderr: stderr.as_bytes().to_vec(),
+            };
+            replies.push_back((output, delay));
+        }
+        self
+    }
+
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls
+            .lock()
+            .map(|calls| calls.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for MockRunner {
+    fn run_with_timeout(
+        &self,
+        program: &str,
+        args: &[&str],
+        env: &[(&str, &str)],
+        _stdin: Option<&[u8]>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Output> {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.push(
+                env.iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .chain(std::iter::once(program.to_string()))
+                    .chain(args.iter().map(|arg| arg.to_string()))
+                    .collect(),
+            );
+        }
+
+        let reply = self
+            .replies
+            .lock()
+            .ok()
+            .and_then(|mut replies| replies.pop_front());
+        let (output, delay) = reply.unwrap_or_else(|| {
+            let output = Output {
+                status: exit_status(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            };
+            (output, Duration::ZERO)
+        });
+
+        if let Some(timeout) = timeout.filter(|timeout| *timeout < delay) {
+            std::thread::sleep(timeout);
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        }
+        std::thread::sleep(delay);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_replies_in_order_then_succeeds() {
+        let runner = MockRunner::new().reply(1, "out", "err");
+        let first = runner.run_with_env("sudo", &["-n", "true"], &[("LANG", "C")], None).unwrap();
+        assert_eq!(first.status.code(), Some(1));
+        assert_eq!((&first.stdout[..], &first.stderr[..]), (&b"out"[..], &b"err"[..]));
+
+        let second = runner.run("sudo", &["-k"], None).unwrap();
+        assert!(second.status.success());
+        assert!(second.stdout.is_empty());
+        assert_eq!(
+            runner.calls(),
+            [vec!["LANG=C", "sudo", "-n", "true"], vec!["sudo", "-k"]]
+        );
+    }
+
+    #[test]
+    fn mock_times_out_a_slow_reply() {
+        let runner = MockRunner::new().reply_after(Duration::from_secs(5), 0, "", "");
+        let error = runner
+            .run_with_timeout("sudo", &["-v"], &[], None, Some(Duration::from_millis(10)))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn system_runner_feeds_stdin_and_reports_the_exit_code() {
+        let output = SystemRunner
+            .run("sh", &["-c", "cat; exit 3"], Some(b"hello"))
+            .unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn system_runner_kills_a_child_past_its_timeout() {
+        let started = Instant::now();
+        let error = SystemRunner
+            .run_with_timeout("sleep", &["5"], &[], None, Some(Duration::from_millis(100)))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,658 @@
+// src-tauri/src/sudo/sudoers.rs
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::CommandRunner;
+
+#[derive(Debug, Serialize)]
+pub struct ValidationResult {
+    pub valid: bool,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct SudoVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    // As sudo prints it, patch level included, e.g. "1.9.15p5"
+    pub raw: String,
+}
+
+impl SudoVersion {
+    // `--preserve-env=A,B`, rather than only the bare flag that keeps everything
+    pub fn supports_preserve_env_list(&self) -> bool {
+        (self.major, self.minor, self.patch) >= (1, 8, 21)
+    }
+}
+
+// visudo usually lives in sbin, which often isn't on a normal user's PATH
+const VISUDO: [&str; 2] = ["visudo", "/usr/sbin/visudo"];
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Checks sudoers content with `visudo -c -f` against a private copy, so
+// nothing ever touches /etc/sudoers itself
+pub fn validate(runner: &dyn CommandRunner, content: &str) -> Result<ValidationResult, String> {
+    let file = TempFile::create(content)
+        .map_err(|e| format!("Failed to write sudoers copy: {}", e))?;
+    let path = file.path.to_string_lossy();
+
+    let mut output = None;
+    for visudo in VISUDO {
+        match runner.run(visudo, &["-c", "-f", &path], None) {
+            Ok(result) => {
+                output = Some(result);
+                break;
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to run visudo: {}", e)),
+        }
+    }
+    let output = output.ok_or("visudo not found, is sudo installed?")?;
+
+    if output.status.success() {
+        return Ok(ValidationResult {
+            valid: true,
+            errors: Vec::new(),
+        });
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(ValidationResult {
+        valid: false,
+        errors: parse_errors(&stderr, &stdout, &path),
+    })
+}
+
+// visudo names the file it checked in every message; swapping our temp path
+// for "sudoers" keeps them readable. Lines that don't mention it are quoted
+// source and caret markers from newer versions, which are dropped unless
+// nothing else came out.
+fn parse_errors(stderr: &str, stdout: &str, path: &str) -> Vec<String> {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .chain(stdout.lines())
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let mut errors: Vec<String> = lines
+        .iter()
+        .filter(|line| line.contains(path))
+        .map(|line| line.replace(path, "sudoers"))
+        .collect();
+    if errors.is_empty() {
+        errors = lines.iter().map(|line| line.to_string()).collect();
+    }
+    if errors.is_empty() {
+        errors.push("visudo rejected the file".to_string());
+    }
+    errors
+}
+
+// Pulls the rules out of `sudo -l`:
+//
+//     Matching Defaults entries for alice on host:
+//         env_reset, secure_path=/usr/sbin\:/usr/bin
+//
+//     User alice may run the following commands on host:
+//         (ALL : ALL) ALL
+//         (root) NOPASSWD: /usr/bin/apt
+//
+// Only the indented lines under the "may run" header are rules; the Defaults
+// section is skipped, as is anything sudo adds after a blank line.
+pub fn parse_permissions(output: &str) -> Vec<String> {
+    let mut permissions = Vec::new();
+    let mut in_rules = false;
+
+    for line in output.lines() {
+        if line.contains("may run the following commands") {
+            in_rules = true;
+        } else if in_rules && line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+            permissions.push(line.trim().to_string());
+        } else if !line.starts_with(char::is_whitespace) {
+            in_rules = false;
+        }
+    }
+    permissions
+}
+
+// Whether rules from parse_permissions let a command run as `target` with no
+// password. Only plain rules are understood:
+//
+//     (root) NOPASSWD: /usr/bin/apt, /usr/bin/systemctl restart nginx
+//     (ALL : ALL) NOPASSWD: ALL
+//
+// A tag carries over to the later commands in its rule until another one
+// overrides it, and a command listed without args allows any. `is_program`
+// says whether a path from a rule is the command's. Aliases and wildcards
+// are never taken as a match, so they only cost a prompt. A command any rule
+// excludes with `!`, as in `NOPASSWD: ALL, !/usr/bin/passwd`, is never
+// allowed, and neither is anything a `!` entry not understood here might
+// exclude.
+pub fn allows_nopasswd(
+    rules: &[String],
+    target: &str,
+    is_program: impl Fn(&str) -> bool,
+    args: &[String],
+) -> bool {
+    let mut allowed = false;
+    for rule in rules {
+        let (runas, commands) = match rule.strip_prefix('(').and_then(|rest| rest.split_once(')')) {
+            Some((runas, commands)) => (runas, commands),
+            None => ("root", rule.as_str()),
+        };
+        let users = runas.split(':').next().unwrap_or_default();
+        if !users.split(',').map(str::trim).any(|user| user == "ALL" || user == target) {
+            continue;
+        }
+
+        let mut nopasswd = false;
+        for spec in commands.split(',') {
+            let mut spec = spec.trim();
+            while let Some((tag, rest)) = spec.split_once(':') {
+                if tag.is_empty() || !tag.chars().all(|c| c.is_ascii_uppercase() || c == '_') {
+                    break;
+                }
+                match tag {
+                    "NOPASSWD" => nopasswd = true,
+                    "PASSWD" => nopasswd = false,
+                    _ => {}
+                }
+                spec = rest.trim_start();
+            }
+            match spec.strip_prefix('!') {
+                Some(negated) if excludes(negated.trim_start(), &is_program, args) => return false,
+                Some(_) => {}
+                None => allowed |= nopasswd && command_matches(spec, &is_program, args),
+            }
+        }
+    }
+    allowed
+}
+
+// Whether a `!` entry rules the command out. One that isn't a plain path
+// might, so it's taken to.
+fn excludes(spec: &str, is_program: impl Fn(&str) -> bool, args: &[String]) -> bool {
+    let plain = spec.starts_with('/') && !spec.contains(['*', '?', '[', '\\']);
+    !plain || command_matches(spec, is_program, args)
+}
+
+fn command_matches(spec: &str, is_program: impl Fn(&str) -> bool, args: &[String]) -> bool {
+    if spec == "ALL" {
+        return true;
+    }
+    if !spec.starts_with('/') || spec.contains(['*', '?', '[', '\\']) {
+        return false;
+    }
+    let mut words = spec.split_whitespace();
+    let program = words.next().unwrap_or_default();
+    let listed: Vec<&str> = words.collect();
+    let args_match = match listed.as_slice() {
+        [] => true,
+        ["\"\""] => args.is_empty(),
+        listed => listed.iter().eq(args.iter()),
+    };
+    args_match && is_program(program)
+}
+
+// The last `timestamp_timeout` set in `sudo -l` output or sudoers content,
+// the way sudo applies them:
+//
+//     Defaults env_reset,timestamp_timeout=5
+//     Defaults:alice timestamp_timeout=2.5
+//
+// Fractions are rounded down, since expiring early only costs an extra
+// prompt, and a negative timeout (never expire) becomes u64::MAX.
+pub fn parse_timestamp_timeout(output: &str) -> Option<u64> {
+    output
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter_map(|entry| entry.strip_prefix("timestamp_timeout="))
+        .filter_map(|value| value.trim_matches('"').parse::<f64>().ok())
+        .next_back()
+        .map(|minutes| if minutes < 0.0 { u64::MAX } else { minutes as u64 })
+}
+
+// Sudoers files in the order sudo reads them, for the rare system where
+// they're readable without root
+pub fn read_config() -> Option<String> {
+    let mut content = fs::read_to_string("/etc/sudoers").ok()?;
+    let mut included: Vec<PathBuf> = fs::read_dir("/etc/sudoers.d")
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        // sudo skips names with a dot or ending in `~`, left by editors
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| !name.contains('.') && !name.ends_with('~'))
+        })
+        .collect();
+    included.sort();
+    for path in included {
+        if let Ok(file) = fs::read_to_string(path) {
+            content.push('\n');
+            content.push_str(&file);
+        }
+    }
+    Some(content)
+}
+
+// What the prompt probe passes as `-p`. sudo expands the escapes a
+// passprompt may use, so their values come back between the separators
+// whenever sudo shows its own prompt. A prompt from PAM replaces it instead.
+pub const PROMPT_PROBE: &str = "\u{1e}%p\u{1f}%u\u{1f}%U\u{1f}%h\u{1f}%H\u{1e}";
+const PROMPT_ESCAPES: [char; 5] = ['p', 'u', 'U', 'h', 'H'];
+
+// sudo's passprompt as it's usually built
+const DEFAULT_PASSPROMPT: &str = "[sudo] password for %p: ";
+
+// The prompt out of what `sudo -S -p PROMPT_PROBE` writes to stderr when
+// stdin is empty:
+//
+//     \x1ealice\x1f...\x1e sudo: no password was provided
+//     sudo: 1 incorrect password attempt
+//
+// When the probe came back, sudo would have shown its own prompt:
+// `passprompt` if given, from sudoers, or else the default, with the
+// escapes filled in. Anything else is PAM's prompt, which has no newline of
+// its own, so sudo's complaint about the missing password may follow it on
+// the same line; that's cut off at the last "sudo: ". Trailing whitespace
+// goes too.
+pub fn parse_prompt(stderr: &str, passprompt: Option<&str>) -> Option<String> {
+    let line = stderr.lines().next()?;
+    if let Some((_, rest)) = line.split_once('\u{1e}') {
+        let (probe, _) = rest.split_once('\u{1e}')?;
+        let values: Vec<&str> = probe.split('\u{1f}').collect();
+        let prompt = expand_prompt(passprompt.unwrap_or(DEFAULT_PASSPROMPT), &values);
+        return Some(prompt.trim_end().to_string()).filter(|prompt| !prompt.is_empty());
+    }
+
+    let prompt = match line.rfind("sudo: ") {
+        Some(complaint) if complaint > 0 => &line[..complaint],
+        _ => line,
+    };
+    let prompt = prompt.trim_end();
+    // Nothing but a complaint means sudo never prompted
+    if prompt.is_empty() || prompt.starts_with("sudo: ") {
+        return None;
+    }
+    Some(prompt.to_string())
+}
+
+// Fills in a passprompt's escapes with the values the probe came back with,
+// in PROMPT_ESCAPES order. `%%` is a literal `%`.
+fn expand_prompt(template: &str, values: &[&str]) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => prompt.push('%'),
+            Some(escape) => match PROMPT_ESCAPES.iter().position(|known| *known == escape) {
+                Some(at) => prompt.push_str(values.get(at).copied().unwrap_or_default()),
+                None => {
+                    prompt.push('%');
+                    prompt.push(escape);
+                }
+            },
+            None => prompt.push('%'),
+        }
+    }
+    prompt
+}
+
+// The last `passprompt` set in sudoers content, quoted or not:
+//
+//     Defaults passprompt="Password for %p@%h: "
+pub fn parse_passprompt(content: &str) -> Option<String> {
+    content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(|line| line.match_indices("passprompt=").map(move |(at, _)| &line[at..]))
+        .filter_map(|setting| {
+            let value = &setting["passprompt=".len()..];
+            match value.strip_prefix('"') {
+                Some(quoted) => quoted.split_once('"').map(|(value, _)| value),
+                None => value.split(|c: char| c == ',' || c.is_whitespace()).next(),
+            }
+        })
+        .last()
+        .map(str::to_string)
+}
+
+// The version on the first line of `sudo -V`:
+//
+//     Sudo version 1.9.15p5
+//     Sudoers policy plugin version 1.9.15p5
+//
+// The `p` patch level only survives in `raw`. Anything else, sudo-rs
+// included, isn't recognized.
+pub fn parse_version(output: &str) -> Option<SudoVersion> {
+    let raw = output.lines().next()?.trim().strip_prefix("Sudo version ")?.trim();
+    let mut numbers = raw.split(|c: char| !c.is_ascii_digit());
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next()?.parse().ok()?;
+    let patch = numbers.next().and_then(|patch| patch.parse().ok()).unwrap_or(0);
+    Some(SudoVersion {
+        major,
+        minor,
+        patch,
+        raw: raw.to_string(),
+    })
+}
+
+// Takes sudo's lecture, printed ahead of the first prompt with `lecture`
+// set, out of its stderr:
+//
+//     We trust you have received the usual lecture from the local System
+//     Administrator. It usually boils down to these three things:
+//
+//         #1) Respect the privacy of others.
+//         #2) Think before you type.
+//         #3) With great power comes great responsibility.
+//
+// Returns the lecture and everything else. A custom lecture_file can't be
+// told apart and stays in the rest, like the insults some setups print in
+// place of "Sorry, try again."; the exit status decides either way.
+pub fn split_lecture(stderr: &str) -> (Option<String>, String) {
+    let lines: Vec<&str> = stderr.lines().collect();
+    let Some(start) = lines
+        .iter()
+        .position(|line| line.contains("We trust you have received the usual lecture"))
+    else {
+        return (None, stderr.to_string());
+    };
+    let last = lines[start..]
+        .iter()
+        .position(|line| line.trim_start().starts_with("#3)"))
+        .map_or(start, |offset| start + offset);
+    // The blank lines sudo sets it off with go too
+    let mut before = start;
+    while before > 0 && lines[before - 1].trim().is_empty() {
+        before -= 1;
+    }
+    let mut end = last + 1;
+    while lines.get(end).is_some_and(|line| line.trim().is_empty()) {
+        end += 1;
+    }
+
+    let lecture = lines[start..=last].join("\n");
+    let rest: Vec<&str> = lines[..before].iter().chain(&lines[end..]).copied().collect();
+    (Some(lecture), rest.join("\n"))
+}
+
+// `sudo -l` for a user with no rules at all, which isn't an error here
+pub fn not_allowed(output: &str) -> bool {
+    output.contains("may not run sudo") || output.contains("is not allowed to run sudo")
+}
+
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(content: &str) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "term-sudoers-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        let temp = Self { path };
+        file.write_all(content.as_bytes())?;
+        Ok(temp)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sudo::MockRunner;
+    use std::path::Path;
+
+    const SUDO_L: &str = "\
+Matching Defaults entries for alice on host:
+    env_reset, secure_path=/usr/sbin\\:/usr/bin, timestamp_timeout=5
+
+User alice may run the following commands on host:
+    (ALL : ALL) ALL
+    (root) NOPASSWD: /usr/bin/apt, /usr/bin/systemctl restart nginx
+";
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        args(rules)
+    }
+
+    #[test]
+    fn reads_only_the_rules_from_sudo_l() {
+        assert_eq!(
+            parse_permissions(SUDO_L),
+            rules(&[
+                "(ALL : ALL) ALL",
+                "(root) NOPASSWD: /usr/bin/apt, /usr/bin/systemctl restart nginx",
+            ])
+        );
+        assert!(parse_permissions("User alice is not allowed to run sudo on host.").is_empty());
+    }
+
+    #[test]
+    fn nopasswd_carries_over_to_later_commands() {
+        let rules = rules(&["(root) NOPASSWD: /usr/bin/apt, /usr/bin/systemctl restart nginx"]);
+        let is = |path: &'static str| move |program: &str| program == path;
+
+        assert!(allows_nopasswd(&rules, "root", is("/usr/bin/apt"), &args(&["update"])));
+        assert!(allows_nopasswd(
+            &rules,
+            "root",
+            is("/usr/bin/systemctl"),
+            &args(&["restart", "nginx"])
+        ));
+        assert!(!allows_nopasswd(
+            &rules,
+            "root",
+            is("/usr/bin/systemctl"),
+            &args(&["stop", "nginx"])
+        ));
+        assert!(!allows_nopasswd(&rules, "bob", is("/usr/bin/apt"), &[]));
+    }
+
+    #[test]
+    fn passwd_overrides_an_earlier_tag() {
+        let rules = rules(&["(ALL) NOPASSWD: /usr/bin/apt, PASSWD: /usr/bin/dnf"]);
+        assert!(allows_nopasswd(&rules, "root", |p| p == "/usr/bin/apt", &[]));
+        assert!(!allows_nopasswd(&rules, "root", |p| p == "/usr/bin/dnf", &[]));
+    }
+
+    #[test]
+    fn only_plain_rules_match() {
+        let any = |_: &str| true;
+        assert!(allows_nopasswd(&rules(&["(ALL : ALL) NOPASSWD: ALL"]), "bob", any, &[]));
+        assert!(!allows_nopasswd(&rules(&["(ALL : ALL) ALL"]), "root", any, &[]));
+        assert!(!allows_nopasswd(&rules(&["(root) NOPASSWD: /usr/bin/*"]), "root", any, &[]));
+        assert!(!allows_nopasswd(&rules(&["(root) NOPASSWD: APT"]), "root", any, &[]));
+        assert!(allows_nopasswd(&rules(&["(root) NOPASSWD: /bin/ls \"\""]), "root", any, &[]));
+        assert!(!allows_nopasswd(
+            &rules(&["(root) NOPASSWD: /bin/ls \"\""]),
+            "root",
+            any,
+            &args(&["-l"])
+        ));
+    }
+
+    #[test]
+    fn negated_commands_are_excluded() {
+        let all_but = rules(&["(ALL) NOPASSWD: ALL, !/usr/bin/passwd"]);
+        assert!(allows_nopasswd(&all_but, "root", |p| p == "/usr/bin/apt", &[]));
+        assert!(!allows_nopasswd(&all_but, "root", |p| p == "/usr/bin/passwd", &[]));
+
+        // Excluded by another rule, or by something that can't be checked
+        let split = rules(&[
+            "(root) NOPASSWD: /usr/bin/passwd",
+            "(ALL) ALL, !/usr/bin/passwd",
+        ]);
+        assert!(!allows_nopasswd(&split, "root", |p| p == "/usr/bin/passwd", &[]));
+        let alias = rules(&["(ALL) NOPASSWD: ALL, !SHELLS"]);
+        assert!(!allows_nopasswd(&alias, "root", |p| p == "/usr/bin/apt", &[]));
+
+        // An exclusion with args leaves other uses of the command alone
+        let narrow = rules(&["(ALL) NOPASSWD: /usr/bin/systemctl, !/usr/bin/systemctl stop"]);
+        let systemctl = |p: &str| p == "/usr/bin/systemctl";
+        assert!(allows_nopasswd(&narrow, "root", systemctl, &args(&["status"])));
+        assert!(!allows_nopasswd(&narrow, "root", systemctl, &args(&["stop"])));
+    }
+
+    #[test]
+    fn the_last_timestamp_timeout_wins() {
+        assert_eq!(parse_timestamp_timeout(SUDO_L), Some(5));
+        let sudoers = "\
+Defaults env_reset,timestamp_timeout=5
+# Defaults timestamp_timeout=60
+Defaults:alice timestamp_timeout=2.5
+";
+        assert_eq!(parse_timestamp_timeout(sudoers), Some(2));
+        assert_eq!(parse_timestamp_timeout("Defaults timestamp_timeout=-1"), Some(u64::MAX));
+        assert_eq!(parse_timestamp_timeout("Defaults env_reset"), None);
+    }
+
+    #[test]
+    fn cuts_the_complaint_off_a_pam_prompt() {
+        let stderr = "Passwort: sudo: no password was provided\n\
+                      sudo: 1 incorrect password attempt\n";
+        assert_eq!(parse_prompt(stderr, None).as_deref(), Some("Passwort:"));
+        assert_eq!(parse_prompt("Password: \n", None).as_deref(), Some("Password:"));
+        assert_eq!(parse_prompt("sudo: a password is required\n", None), None);
+        assert_eq!(parse_prompt("", None), None);
+    }
+
+    #[test]
+    fn fills_in_sudos_own_prompt() {
+        let stderr = "\u{1e}root\u{1f}alice\u{1f}root\u{1f}box\u{1f}box.lan\u{1e}\
+                      sudo: no password was provided\n";
+        assert_eq!(
+            parse_prompt(stderr, None).as_deref(),
+            Some("[sudo] password for root:")
+        );
+        assert_eq!(
+            parse_prompt(stderr, Some("%u@%H wants 100%% of %p's password: ")).as_deref(),
+            Some("alice@box.lan wants 100% of root's password:")
+        );
+        assert_eq!(parse_prompt(stderr, Some("%x %")).as_deref(), Some("%x %"));
+    }
+
+    #[test]
+    fn reads_passprompt_from_sudoers() {
+        let sudoers = "\
+Defaults passprompt=Password:
+# Defaults passprompt=\"commented out\"
+Defaults:alice passprompt=\"Password for %p@%h: \", passprompt_override
+";
+        assert_eq!(parse_passprompt(sudoers).as_deref(), Some("Password for %p@%h: "));
+        let unquoted = parse_passprompt("Defaults passprompt=Pass:,env_reset");
+        assert_eq!(unquoted.as_deref(), Some("Pass:"));
+        assert_eq!(parse_passprompt("Defaults env_reset"), None);
+    }
+
+    #[test]
+    fn parses_sudo_versions() {
+        let version = parse_version("Sudo version 1.9.15p5\nSudoers policy plugin version 1.9.15p5")
+            .unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 9, 15));
+        assert_eq!(version.raw, "1.9.15p5");
+        assert!(version.supports_preserve_env_list());
+
+        let old = parse_version("Sudo version 1.8.19").unwrap();
+        assert!(!old.supports_preserve_env_list());
+        assert_eq!(parse_version("sudo-rs 0.2.2"), None);
+    }
+
+    #[test]
+    fn takes_the_lecture_out_of_stderr() {
+        let stderr = [
+            "",
+            "We trust you have received the usual lecture from the local System",
+            "Administrator. It usually boils down to these three things:",
+            "",
+            "    #1) Respect the privacy of others.",
+            "    #2) Think before you type.",
+            "    #3) With great power comes great responsibility.",
+            "",
+            "Sorry, try again.",
+        ]
+        .join("\n");
+        let (lecture, rest) = split_lecture(&stderr);
+        let lecture = lecture.unwrap();
+        assert!(lecture.starts_with("We trust you"));
+        assert!(lecture.ends_with("great responsibility."));
+        assert_eq!(rest, "Sorry, try again.");
+
+        assert_eq!(split_lecture("Sorry, try again."), (None, "Sorry, try again.".to_string()));
+    }
+
+    #[test]
+    fn recognizes_users_without_rules() {
+        assert!(not_allowed("User bob is not allowed to run sudo on host."));
+        assert!(not_allowed("Sorry, user bob may not run sudo on host."));
+        assert!(!not_allowed(SUDO_L));
+    }
+
+    #[test]
+    fn validates_a_private_copy_with_visudo() {
+        let runner = MockRunner::new().reply(0, "parsed OK", "");
+        let result = validate(&runner, "root ALL=(ALL) ALL\n").unwrap();
+        assert!(result.valid);
+
+        let calls = runner.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][..3], ["visudo", "-c", "-f"]);
+        let copy = Path::new(&calls[0][3]);
+        assert!(copy.starts_with(std::env::temp_dir()));
+        assert!(!copy.exists());
+    }
+
+    #[test]
+    fn reports_visudo_errors_against_sudoers() {
+        let path = "/tmp/term-sudoers-1-0";
+        let stderr = format!(
+            "{path}:2:5: syntax error\nroot ALL=(ALL\n    ^\n{path}: bad permissions\n"
+        );
+        assert_eq!(
+            parse_errors(&stderr, "", path),
+            ["sudoers:2:5: syntax error", "sudoers: bad permissions"]
+        );
+        assert_eq!(parse_errors("", "", path), ["visudo rejected the file"]);
+
+        let runner = MockRunner::new().reply(1, "", "visudo: sudoers file busy\n");
+        let result = validate(&runner, "").unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.errors, ["visudo: sudoers file busy"]);
+    }
+}
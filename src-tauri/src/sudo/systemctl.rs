@@ -0,0 +1,149 @@
+// src-tauri/src/sudo/systemctl.rs
+use serde::Serialize;
+
+// What sudo_systemctl_status makes of `systemctl status`. Fields that
+// weren't in the output are None. When not even the Active: line could be
+// found, `raw` has the output as systemctl printed it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct UnitStatus {
+    pub unit: String,
+    // "active", "inactive", "failed", ...
+    pub active: Option<String>,
+    // "running", "exited", "dead", ...
+    pub sub_state: Option<String>,
+    // The unit file's state: "enabled", "disabled", "static", "masked", ...
+    pub enabled: Option<String>,
+    pub main_pid: Option<u32>,
+    pub memory_bytes: Option<u64>,
+    pub raw: Option<String>,
+}
+
+// Reads the status block, e.g.
+//
+//   ● ssh.service - OpenBSD Secure Shell server
+//        Loaded: loaded (/lib/systemd/system/ssh.service; enabled; preset: enabled)
+//        Active: active (running) since Tue 2024-05-14 09:12:01 UTC; 2h ago
+//      Main PID: 812 (sshd)
+//        Memory: 5.6M (peak: 7.1M)
+//
+// The labels aren't translated, so this holds in any locale.
+pub fn parse_status(unit: &str, output: &str) -> UnitStatus {
+    let mut status = UnitStatus {
+        unit: unit.to_string(),
+        ..Default::default()
+    };
+    for line in output.lines() {
+        let Some((label, value)) = line.trim_start().split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let first_word = value.split_whitespace().next();
+        match label {
+            "Loaded" => status.enabled = unit_file_state(value),
+            "Active" => {
+                status.active = first_word.map(str::to_string);
+                status.sub_state = sub_state(value);
+            }
+            "Main PID" => status.main_pid = first_word.and_then(|pid| pid.parse().ok()),
+            "Memory" => status.memory_bytes = first_word.and_then(parse_size),
+            _ => {}
+        }
+    }
+    if status.active.is_none() {
+        status.raw = Some(output.to_string());
+    }
+    status
+}
+
+// "loaded (/lib/systemd/system/ssh.service; enabled; preset: enabled)". A
+// masked unit has no file to describe: "masked (Reason: Unit ... is masked.)"
+fn unit_file_state(loaded: &str) -> Option<String> {
+    let (state, details) = loaded.split_once(' ').unwrap_or((loaded, ""));
+    if state == "masked" {
+        return Some(state.to_string());
+    }
+    let details = details.strip_prefix('(')?.strip_suffix(')')?;
+    let file_state = details.split(';').nth(1)?.trim();
+    (!file_state.is_empty()).then(|| file_state.to_string())
+}
+
+// The "(running)" in "active (running) since ...". A failed unit has its
+// result there instead, "(Result: exit-code)", which isn't a sub-state.
+fn sub_state(active: &str) -> Option<String> {
+    let (_, rest) = active.split_once('(')?;
+    let (sub_state, _) = rest.split_once(')')?;
+    (!sub_state.is_empty() && !sub_state.contains([' ', ':'])).then(|| sub_state.to_string())
+}
+
+// As systemd formats sizes: "512B", "640.0K", "5.6M", in powers of 1024
+fn parse_size(size: &str) -> Option<u64> {
+    let suffix = size.chars().last()?;
+    let exponent = "BKMGTPE".find(suffix)?;
+    let number: f64 = size[..size.len() - 1].parse().ok()?;
+    Some((number * 1024f64.powi(exponent as i32)) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_running_unit() {
+        let output = "\
+● ssh.service - OpenBSD Secure Shell server
+     Loaded: loaded (/lib/systemd/system/ssh.service; enabled; preset: enabled)
+     Active: active (running) since Tue 2024-05-14 09:12:01 UTC; 2h ago
+   Main PID: 812 (sshd)
+     Memory: 5.6M (peak: 7.1M)
+";
+        let status = parse_status("ssh.service", output);
+        assert_eq!(status.active.as_deref(), Some("active"));
+        assert_eq!(status.sub_state.as_deref(), Some("running"));
+        assert_eq!(status.enabled.as_deref(), Some("enabled"));
+        assert_eq!(status.main_pid, Some(812));
+        assert_eq!(status.memory_bytes, Some((5.6 * 1024.0 * 1024.0) as u64));
+        assert_eq!(status.raw, None);
+    }
+
+    #[test]
+    fn a_failed_result_is_not_a_sub_state() {
+        let output = "\
+× nginx.service - A high performance web server
+     Loaded: loaded (/lib/systemd/system/nginx.service; disabled; preset: enabled)
+     Active: failed (Result: exit-code) since Tue 2024-05-14 09:12:01 UTC; 5s ago
+";
+        let status = parse_status("nginx.service", output);
+        assert_eq!(status.active.as_deref(), Some("failed"));
+        assert_eq!(status.sub_state, None);
+        assert_eq!(status.enabled.as_deref(), Some("disabled"));
+        assert_eq!(status.main_pid, None);
+    }
+
+    #[test]
+    fn reads_a_masked_unit() {
+        let output = "\
+○ cups.service
+     Loaded: masked (Reason: Unit cups.service is masked.)
+     Active: inactive (dead)
+";
+        let status = parse_status("cups.service", output);
+        assert_eq!(status.enabled.as_deref(), Some("masked"));
+        assert_eq!(status.sub_state.as_deref(), Some("dead"));
+    }
+
+    #[test]
+    fn keeps_unrecognized_output_raw() {
+        let output = "Unit nope.service could not be found.\n";
+        let status = parse_status("nope.service", output);
+        assert_eq!(status.active, None);
+        assert_eq!(status.raw.as_deref(), Some(output));
+    }
+
+    #[test]
+    fn parses_systemd_sizes() {
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("640.0K"), Some(640 * 1024));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+        assert_eq!(parse_size("12"), None);
+    }
+}
@@ -0,0 +1,174 @@
+// src-tauri/src/sudo/uac.rs
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg_attr(not(windows), allow(dead_code))]
+pub enum Outcome {
+    Exited(u32),
+    // The user said no in the consent dialog
+    Declined,
+    TimedOut,
+}
+
+// Starts the command through ShellExecuteEx with the `runas` verb, which shows
+// the UAC consent dialog, and waits for it to exit. The elevated process gets
+// its own console, so its output can't be captured.
+#[cfg(windows)]
+pub fn run(
+    command: &str,
+    args: &[String],
+    cwd: Option<&Path>,
+    timeout: Option<Duration>,
+) -> Result<Outcome, String> {
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, GetLastError, ERROR_CANCELLED, WAIT_TIMEOUT,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, TerminateProcess, WaitForSingleObject, INFINITE,
+    };
+    use windows_sys::Win32::UI::Shell::{
+        ShellExecuteExW, SEE_MASK_NOASYNC, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::SW_HIDE;
+
+    let verb = wide("runas");
+    let file = wide(command);
+    let params = wide(
+        args.iter()
+            .map(|arg| quote_arg(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    let dir = cwd.map(|cwd| wide(cwd.as_os_str()));
+
+    // SAFETY: SHELLEXECUTEINFOW is a plain C struct for which all zeroes is valid
+    let mut info: SHELLEXECUTEINFOW = unsafe { std::mem::zeroed() };
+    info.cbSize = std::mem::size_of::<SHELLEXECUTEINFOW>() as u32;
+    info.fMask = SEE_MASK_NOCLOSEPROCESS | SEE_MASK_NOASYNC;
+    info.lpVerb = verb.as_ptr();
+    info.lpFile = file.as_ptr();
+    info.lpParameters = params.as_ptr();
+    info.lpDirectory = dir.as_ref().map_or(std::ptr::null(), |dir| dir.as_ptr());
+    info.nShow = SW_HIDE;
+
+    // SAFETY: every string pointer in `info` outlives the call
+    if unsafe { ShellExecuteExW(&mut info) } == 0 {
+        let code = unsafe { GetLastError() };
+        if code == ERROR_CANCELLED {
+            return Ok(Outcome::Declined);
+        }
+        return Err(format!(
+            "Failed to execute command: {}",
+            std::io::Error::from_raw_os_error(code as i32)
+        ));
+    }
+
+    // No process handle means the verb was handed to something that didn't
+    // start a process, so there's nothing to wait for
+    let process = info.hProcess;
+    if process.is_null() {
+        return Ok(Outcome::Exited(0));
+    }
+
+    let wait_ms = timeout.map_or(INFINITE, |timeout| {
+        u32::try_from(timeout.as_millis()).unwrap_or(INFINITE - 1)
+    });
+
+    // SAFETY: `process` is a valid handle owned by us until CloseHandle
+    let outcome = unsafe {
+        if WaitForSingleObject(process, wait_ms) == WAIT_TIMEOUT {
+            TerminateProcess(process, 1);
+            Outcome::TimedOut
+        } else {
+            let mut exit_code = 0u32;
+            GetExitCodeProcess(process, &mut exit_code);
+            Outcome::Exited(exit_code)
+        }
+    };
+    unsafe { CloseHandle(process) };
+
+    Ok(outcome)
+}
+
+#[cfg(not(windows))]
+pub fn run(
+    _command: &str,
+    _args: &[String],
+    _cwd: Option<&Path>,
+    _timeout: Option<Duration>,
+) -> Result<Outcome, String> {
+    Err("UAC elevation is only available on Windows".to_string())
+}
+
+// Whether the app's own token is already elevated
+#[cfg(windows)]
+pub fn is_elevated() -> bool {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::Security::{
+        GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY,
+    };
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    let mut token = std::ptr::null_mut();
+    // SAFETY: the pseudo-handle from GetCurrentProcess needs no closing
+    if unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) } == 0 {
+        return false;
+    }
+
+    let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+    let mut size = 0u32;
+    // SAFETY: the buffer is a TOKEN_ELEVATION of exactly the size passed in
+    let ok = unsafe {
+        GetTokenInformation(
+            token,
+            TokenElevation,
+            &mut elevation as *mut TOKEN_ELEVATION as *mut _,
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size,
+        )
+    };
+    unsafe { CloseHandle(token) };
+
+    ok != 0 && elevation.TokenIsElevated != 0
+}
+
+#[cfg(not(windows))]
+pub fn is_elevated() -> bool {
+    false
+}
+
+#[cfg(windows)]
+fn wide(s: impl AsRef<std::ffi::OsStr>) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    s.as_ref().encode_wide().chain(std::iter::once(0)).collect()
+}
+
+// ShellExecute takes a single parameter string, which the target splits back
+// up with the MSVC runtime's rules. Backslashes only need escaping before a quote.
+#[cfg(windows)]
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '"']) {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
@@ -5,17 +5,76 @@ import { useState, useCallback } from 'react';
 interface SudoRequest {
   command: string;
   args: string[];
+  // The raw password, with no line ending: it's sent exactly as given, and
+  // one containing \r or \n is rejected
   password?: string;
+  env?: Record<string, string>;
+  preserve_env?: string[];
+  cwd?: string;
+  timeout_secs?: number;
+  stdin?: string;
+  dry_run?: boolean;
+  merge_streams?: boolean;
+  run_as?: string;
+  login_shell?: boolean;
+  max_output_bytes?: number;
+  raw_output?: boolean;
+  progress_parser?: 'apt' | 'dnf' | null;
+  request_id?: string;
+  capture_env?: boolean;
+  interactive?: boolean;
+  use_pty?: boolean;
+  confirmation_token?: string;
+  askpass?: string;
+  output_encoding?: string;
+  reason?: string;
+  capture_stdout?: boolean;
+  capture_stderr?: boolean;
 }
 
+type SudoError =
+  | { kind: 'password_required' }
+  | { kind: 'invalid_password' }
+  | { kind: 'locked_out' }
+  | { kind: 'not_permitted' }
+  | { kind: 'timeout' }
+  | { kind: 'spawn_failed'; detail: string }
+  | { kind: 'backend'; detail: string }
+  | { kind: 'unknown_user'; detail: string }
+  | { kind: 'account_locked' }
+  | { kind: 'password_expired' }
+  | { kind: 'cancelled' }
+  | { kind: 'reauth_pending' }
+  | { kind: 'rate_limited' }
+  | { kind: 'too_many_prompts' }
+  | { kind: 'no_sudo_binary' }
+  | { kind: 'confirmation_required'; detail: string };
+
 interface SudoResponse {
   success: boolean;
   output: string;
   error?: string;
+  error_kind?: SudoError | null;
   cached: boolean;
   needs_password: boolean;
+  exit_code?: number | null;
+  signal?: number | null;
+  method?: string;
+  planned_command?: string[] | null;
+  truncated?: boolean;
+  // base64
+  output_bytes?: string | null;
+  stderr?: string | null;
+  duration_ms?: number;
+  auth_duration_ms?: number | null;
+  effective_env?: Record<string, string> | null;
+  executed_command?: string[];
+  friendly_label?: string | null;
+  escalation_warning?: string | null;
 }
 
+type PrivilegeStatus = 'available' | 'needs_password' | 'not_in_sudoers' | 'no_sudo_binary';
+
 export const useFastSudo = () => {
   const [isLoading, setIsLoading] = useState(false);
   const [needsPassword, setNeedsPassword] = useState(false);
@@ -34,6 +93,7 @@ export const useFastSudo = () => {
         success: false,
         output: '',
         error: `Failed to execute sudo command: ${error}`,
+        error_kind: { kind: 'spawn_failed', detail: String(error) },
         cached: false,
         needs_password: false,
       };
@@ -42,21 +102,22 @@ export const useFastSudo = () => {
     }
   }, []);
 
-  const clearCache = useCallback(async (): Promise<void> => {
+  // systemKill also runs `sudo -k`, dropping the timestamp outside the app too
+  const clearCache = useCallback(async (systemKill = false): Promise<void> => {
     try {
-      await invoke('clear_sudo_cache');
+      await invoke('clear_sudo_cache', { systemKill });
       setNeedsPassword(false);
     } catch (error) {
       console.error('Failed to clear sudo cache:', error);
     }
   }, []);
 
-  const checkPrivileges = useCallback(async (): Promise<boolean> => {
+  const checkPrivileges = useCallback(async (): Promise<PrivilegeStatus> => {
     try {
-      return await invoke<boolean>('check_sudo_privileges');
+      return await invoke<PrivilegeStatus>('check_sudo_privileges');
     } catch (error) {
       console.error('Failed to check sudo privileges:', error);
-      return false;
+      return 'needs_password';
     }
   }, []);
 